@@ -0,0 +1,22 @@
+// XO-CHIP audio state.
+//
+// While the sound timer is non-zero, XO-CHIP plays back a 128-bit pattern
+// (loaded with F002) one bit at a time, at a sample rate set with Fx3A
+// (4000 * 2^((pitch - 64) / 48) Hz). The terminal frontends can only beep,
+// so this is kept for save states, and for frontends that can do better.
+
+pub struct Audio {
+    pub pattern: [u8; 16],
+    pub pitch: u8,
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        Audio {
+            // the default pattern is a plain square wave
+            pattern: [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+                      0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF],
+            pitch: 64,
+        }
+    }
+}
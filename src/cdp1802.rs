@@ -0,0 +1,362 @@
+// RCA CDP1802 CPU core.
+//
+// The COSMAC VIP's CPU. Everything outside the chip (memory, I/O ports, and
+// the EF flag inputs) is reached through a Bus, so the same core could drive
+// other 1802 machines too.
+//
+// Timing is tracked in machine cycles (8 clocks each): every instruction
+// takes 2, except for long branches / skips, which take 3.
+
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+
+    fn output(&mut self, port: u8, val: u8); // OUT 1-7
+    fn input(&mut self, port: u8) -> u8; // INP 1-7
+
+    fn ef(&self, flag: u8) -> bool; // EF1-4 flag inputs
+}
+
+pub struct Cdp1802 {
+    pub r: [u16; 16], // scratchpad registers
+    pub p: u8,        // selects the program counter
+    pub x: u8,        // selects the data pointer
+    pub d: u8,        // accumulator
+    pub df: bool,     // carry / borrow
+    pub t: u8,        // X and P, saved by interrupts
+    pub ie: bool,     // interrupts enabled
+    pub q: bool,      // output flip-flop
+
+    pub idle: bool, // stopped by IDL, until the next interrupt / DMA
+}
+
+impl Cdp1802 {
+    // State after a reset
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+
+            idle: false,
+        }
+    }
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> u8 {
+        let pc = self.p as usize;
+        let byte = bus.read(self.r[pc]);
+        self.r[pc] = self.r[pc].wrapping_add(1);
+        byte
+    }
+
+    // Services an interrupt request. Returns false if interrupts are off.
+    pub fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    // A DMA out cycle: hands the byte at R0 to a peripheral
+    pub fn dma_out(&mut self, bus: &mut dyn Bus) -> u8 {
+        let byte = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        byte
+    }
+
+    // Runs one instruction, and returns how many machine cycles it took
+    pub fn step(&mut self, bus: &mut dyn Bus) -> u32 {
+        if self.idle {
+            return 2;
+        }
+
+        let instr = self.fetch(bus);
+        let n = (instr & 0xF) as usize;
+        let rx = self.x as usize;
+
+        match instr >> 4 {
+            0x0 if n == 0 => self.idle = true, // IDL
+            0x0 => self.d = bus.read(self.r[n]), // LDN
+            0x1 => self.r[n] = self.r[n].wrapping_add(1), // INC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1), // DEC
+            0x3 => {
+                let taken = self.condition(bus, n);
+                let pc = self.p as usize;
+                if taken {
+                    let target = bus.read(self.r[pc]);
+                    self.r[pc] = self.r[pc] & 0xFF00 | target as u16;
+                } else {
+                    self.r[pc] = self.r[pc].wrapping_add(1);
+                }
+            }
+            0x4 => {
+                // LDA
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[n], self.d), // STR
+            0x6 => match n {
+                0x0 => self.r[rx] = self.r[rx].wrapping_add(1), // IRX
+                0x1..=0x7 => {
+                    // OUT
+                    let val = bus.read(self.r[rx]);
+                    self.r[rx] = self.r[rx].wrapping_add(1);
+                    bus.output(n as u8, val);
+                }
+                0x8 => (), // not an 1802 instruction
+                _ => {
+                    // INP
+                    let val = bus.input(n as u8 - 8);
+                    bus.write(self.r[rx], val);
+                    self.d = val;
+                }
+            },
+            0x7 => self.group_7(bus, n),
+            0x8 => self.d = self.r[n] as u8, // GLO
+            0x9 => self.d = (self.r[n] >> 8) as u8, // GHI
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16, // PLO
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8, // PHI
+            0xC => {
+                self.long_branch(bus, n);
+                return 3;
+            }
+            0xD => self.p = n as u8, // SEP
+            0xE => self.x = n as u8, // SEX
+            0xF if n == 0x6 => {
+                // SHR (no operand)
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            0xF if n == 0xE => {
+                // SHL (no operand)
+                self.df = self.d >> 7 != 0;
+                self.d <<= 1;
+            }
+            0xF => {
+                // ALU ops, with the operand at M(R(X)) (or immediate, at
+                // M(R(P)), for F8 and up)
+                let operand = if n < 8 {
+                    bus.read(self.r[rx])
+                } else {
+                    self.fetch(bus)
+                };
+                self.alu(n & 7, operand);
+            }
+            _ => unreachable!(),
+        }
+
+        2
+    }
+
+    // Condition for short branches (3N) and long branches (CN)
+    fn condition(&self, bus: &dyn Bus, n: usize) -> bool {
+        let cond = match n & 7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.ef(flag as u8 - 3),
+        };
+
+        // the top half of the group negates the condition
+        if n & 8 != 0 {
+            !cond
+        } else {
+            cond
+        }
+    }
+
+    fn long_branch(&mut self, bus: &mut dyn Bus, n: usize) {
+        let pc = self.p as usize;
+
+        // C4 is NOP, and C5-C7, CC-CF are long skips, with their own
+        // conditions
+        let skip = match n {
+            0x4 => return,
+            0x5 => Some(!self.q),
+            0x6 => Some(self.d != 0),
+            0x7 => Some(!self.df),
+            0x8 => Some(true),
+            0xC => Some(self.ie),
+            0xD => Some(self.q),
+            0xE => Some(self.d == 0),
+            0xF => Some(self.df),
+            _ => None,
+        };
+
+        match skip {
+            Some(true) => self.r[pc] = self.r[pc].wrapping_add(2),
+            Some(false) => (),
+            None if self.condition(bus, n) => {
+                let hi = bus.read(self.r[pc]);
+                let lo = bus.read(self.r[pc].wrapping_add(1));
+                self.r[pc] = (hi as u16) << 8 | lo as u16;
+            }
+            None => self.r[pc] = self.r[pc].wrapping_add(2),
+        }
+    }
+
+    fn group_7(&mut self, bus: &mut dyn Bus, n: usize) {
+        let rx = self.x as usize;
+
+        match n {
+            0x0 | 0x1 => {
+                // RET / DIS
+                let xp = bus.read(self.r[rx]);
+                self.r[rx] = self.r[rx].wrapping_add(1);
+                self.x = xp >> 4;
+                self.p = xp & 0xF;
+                self.ie = n == 0;
+            }
+            0x2 => {
+                // LDXA
+                self.d = bus.read(self.r[rx]);
+                self.r[rx] = self.r[rx].wrapping_add(1);
+            }
+            0x3 => {
+                // STXD
+                bus.write(self.r[rx], self.d);
+                self.r[rx] = self.r[rx].wrapping_sub(1);
+            }
+            0x6 => {
+                // SHRC
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            0x8 => bus.write(self.r[rx], self.t), // SAV
+            0x9 => {
+                // MARK
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            0xA => self.q = false, // REQ
+            0xB => self.q = true,  // SEQ
+            0xE => {
+                // SHLC
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            _ => {
+                // ADC, SDB, SMB, and their immediate versions
+                let operand = if n < 8 {
+                    bus.read(self.r[rx])
+                } else {
+                    self.fetch(bus)
+                };
+                self.alu_with_carry(n & 7, operand);
+            }
+        }
+    }
+
+    // F0 - F7 (and F8 - FF, with an immediate operand), except the shifts
+    fn alu(&mut self, op: usize, m: u8) {
+        match op {
+            0x0 => self.d = m, // LDX / LDI
+            0x1 => self.d |= m,
+            0x2 => self.d &= m,
+            0x3 => self.d ^= m,
+            0x4 => self.add(m, self.d, false),
+            0x5 => self.sub(m, self.d, true), // SD: M - D
+            0x7 => self.sub(self.d, m, true), // SM: D - M
+            _ => unreachable!(),
+        }
+    }
+
+    // 74 - 77 (and 7C - 7F, with an immediate operand)
+    fn alu_with_carry(&mut self, op: usize, m: u8) {
+        let df = self.df;
+        match op {
+            0x4 => self.add(m, self.d, df),
+            0x5 => self.sub(m, self.d, df),
+            0x7 => self.sub(self.d, m, df),
+            _ => unreachable!(),
+        }
+    }
+
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // DF is set when there is *no* borrow
+    fn sub(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let diff = a as i16 - b as i16 - !no_borrow as i16;
+        self.d = diff as u8;
+        self.df = diff >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64K of RAM, counting reads
+    struct TestBus {
+        mem: Vec<u8>,
+        reads: usize,
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.reads += 1;
+            self.mem[addr as usize]
+        }
+        fn write(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
+
+        fn output(&mut self, _port: u8, _val: u8) {}
+        fn input(&mut self, _port: u8) -> u8 {
+            0
+        }
+
+        fn ef(&self, _flag: u8) -> bool {
+            false
+        }
+    }
+
+    // Runs a single shift instruction from address 0, starting with D = `d`
+    fn shift(opcode: u8, d: u8) -> (Cdp1802, TestBus) {
+        let mut bus = TestBus { mem: vec![0; 0x10000], reads: 0 };
+        bus.mem[0] = opcode;
+        bus.mem[1] = 0xFF; // must not be used as an operand
+
+        let mut cpu = Cdp1802::new();
+        cpu.d = d;
+        cpu.step(&mut bus);
+        (cpu, bus)
+    }
+
+    #[test]
+    fn shl_has_no_operand() {
+        let (cpu, bus) = shift(0xFE, 0x81);
+        assert_eq!((cpu.d, cpu.df), (0x02, true));
+        assert_eq!(cpu.r[0], 1);
+        assert_eq!(bus.reads, 1); // just the opcode
+    }
+
+    #[test]
+    fn shr_has_no_operand() {
+        let (cpu, bus) = shift(0xF6, 0x81);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        assert_eq!(cpu.r[0], 1);
+        assert_eq!(bus.reads, 1);
+    }
+}
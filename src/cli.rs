@@ -0,0 +1,366 @@
+use display;
+use display::Palette;
+use frontend;
+use quirks;
+use quirks::Quirks;
+
+// Everything that can be configured from the command line
+
+pub struct Options {
+    pub rom: String,
+
+    pub frontend: String,
+    pub palette: Palette, // colors, for the frontends that do color
+    pub scale: usize,     // pixel size, for the image frontends
+    pub quirks: Quirks,
+    pub xo_chip: bool,
+
+    pub ipf: u32,      // instructions per frame
+    pub timer_hz: u32, // frames (and timer ticks) per second
+    pub vip_timing: bool, // run at the speed of the COSMAC VIP instead
+
+    pub vip_interpreter: Option<String>, // emulate a whole COSMAC VIP
+    pub vip_monitor: Option<String>,
+
+    pub load_addr: u16,
+    pub seed: Option<u64>,
+
+    pub paused: bool,
+    pub frames: Option<u64>, // stop after this many frames
+
+    pub debug: bool, // start in the debugger
+    pub conformance: bool, // run the test ROMs in the given directory
+    pub regress: bool,     // run the golden-frame regression suite
+    pub bless: bool,       // ...and update the golden frames
+
+    pub headless: bool,             // run flat out, without a frontend
+    pub input_script: Option<String>,
+    pub record: Option<String>, // record key presses into a movie
+    pub dump: Option<String>, // where to dump the final screen
+    pub crash_report: Option<String>, // where to save the crash report
+
+    pub load_state: Option<String>,
+    pub rewind_secs: u32, // how much history to keep for rewinding
+}
+
+pub fn usage() -> String {
+    format!("\
+Usage: ac8e [options] <romfile>
+       ac8e --conformance [romdir]
+       ac8e --regress [--bless] [romdir]
+
+Options:
+    -h, --help              print this message
+    --frontend <name>       one of: {frontends} (default: {default})
+    --palette <colors>      colors of the ansi / sixel / kitty frontends, as
+                            4 hex RGB colors for pixel values 0-3 (default:
+                            000000,ffffff,aaaaaa,555555)
+    --scale <n>             size of a pixel in the sixel / kitty frontends,
+                            in screen pixels, up to 32 (default: 8, hires
+                            pixels are half as big, rounded down)
+    --quirks <preset>       one of: {presets} (default: ac8e)
+    --xo-chip               enable 64 KiB of RAM for XO-CHIP programs
+                            (implies --quirks xochip)
+    --stack-wrap            overflowing the stack drops the oldest return
+                            address, instead of crashing
+    --ipf <n>               instructions executed per frame (default: 10)
+    --hz <n>                instructions executed per second
+                            (overrides --ipf)
+    --timer-hz <n>          frames / timer ticks per second (default: 60)
+    --vip-timing            run as fast as the original COSMAC VIP, with
+                            per-instruction timings (overrides --ipf)
+    --vip <interpreter>     emulate a whole COSMAC VIP (1802 CPU and 1861
+                            display), running the given CHIP-8 interpreter
+    --vip-monitor <rom>     with --vip, use a real monitor ROM
+    --load-addr <addr>      where to load the ROM, and start executing
+                            (default: 0x200, ETI-660 ROMs use 0x600)
+    --seed <n>              seed for the random number generator
+    --paused                start paused (F2 to pause / resume)
+    --frames <n>            exit after running n frames
+    --debug                 start in the debugger (F3 to break into it)
+    --conformance           run the SCTEST and BC_test ROMs (found in romdir,
+                            default: roms) and check their results
+    --regress               run every game in romdir/games, and compare
+                            the final screens with romdir/golden
+    --bless                 with --regress, update the golden screens
+    --headless              run as fast as possible, without a terminal,
+                            then print the final screen (needs --frames)
+    --input <script>        feed key presses from a script, with lines of
+                            `<frame> <keys...>` (`-` releases all keys),
+                            or play back a movie
+    --record <movie>        record key presses into a movie, which can be
+                            played back with --input
+    --dump <file>           save the final screen as text, or an image if
+                            file ends with .pbm / .ppm (`-` for stdout)
+    --crash-report <file>   if the CPU crashes, also save the crash report
+                            (registers, trace, memory, screen) to file
+    --load-state <file>     resume from a save state
+    --rewind <secs>         seconds of history kept for rewinding (F4),
+                            0 to disable (default: 30)",
+            frontends = frontend::FRONTENDS.join(", "),
+            default = frontend::DEFAULT_FRONTEND,
+            presets = quirks::PRESETS.join(", "))
+}
+
+// Parses decimal, or hex if prefixed with `0x`, up to `max`
+fn parse_num(flag: &str, val: &str, max: u64) -> Result<u64, String> {
+    let parsed = match val.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => val.parse::<u64>(),
+    };
+
+    match parsed {
+        Ok(num) if num <= max => Ok(num),
+        Ok(_) => Err(format!("{} is out of range for {} (at most {})",
+                             val,
+                             flag,
+                             max)),
+        Err(_) => Err(format!("invalid number for {}: '{}'", flag, val)),
+    }
+}
+
+// Parses 4 comma-separated hex RGB colors, eg: `000000,ffffff,aaaaaa,555555`
+fn parse_palette(val: &str) -> Result<Palette, String> {
+    let err = || format!("invalid palette '{}', expected 4 hex RGB colors \
+                          separated by commas",
+                         val);
+
+    let colors = val.split(',').collect::<Vec<&str>>();
+    if colors.len() != 4 {
+        return Err(err());
+    }
+
+    let mut palette = [[0; 3]; 4];
+    for (rgb, color) in palette.iter_mut().zip(colors) {
+        let hex = color.trim().trim_start_matches('#');
+        // (from_str_radix would also take a leading '+')
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        let color = u32::from_str_radix(hex, 16).map_err(|_| err())?;
+        *rgb = [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+    }
+    Ok(palette)
+}
+
+// Returns Ok(None) if the user asked for help
+pub fn parse(args: &[String]) -> Result<Option<Options>, String> {
+    let mut opts = Options {
+        rom: String::new(),
+
+        frontend: frontend::DEFAULT_FRONTEND.to_string(),
+        palette: display::PALETTE,
+        scale: 8,
+        quirks: Quirks::default(),
+        xo_chip: false,
+
+        ipf: 10,
+        timer_hz: 60,
+        vip_timing: false,
+
+        vip_interpreter: None,
+        vip_monitor: None,
+
+        load_addr: 0x200,
+        seed: None,
+
+        paused: false,
+        frames: None,
+
+        debug: false,
+        conformance: false,
+        regress: false,
+        bless: false,
+
+        headless: false,
+        input_script: None,
+        record: None,
+        dump: None,
+        crash_report: None,
+
+        load_state: None,
+        rewind_secs: 30,
+    };
+
+    let mut rom = None;
+    let mut quirks = None;
+    let mut hz = None;
+    let mut stack_wrap = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if rom.is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            rom = Some(arg.clone());
+            continue;
+        }
+
+        // support both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.find('=') {
+            Some(i) => (&arg[..i], Some(arg[i + 1..].to_string())),
+            None => (&arg[..], None),
+        };
+
+        match flag {
+            "-h" | "--help" => return Ok(None),
+            "--xo-chip" => opts.xo_chip = true,
+            "--paused" => opts.paused = true,
+            "--debug" => opts.debug = true,
+            "--headless" => opts.headless = true,
+            "--vip-timing" => opts.vip_timing = true,
+            "--stack-wrap" => stack_wrap = true,
+            "--conformance" => opts.conformance = true,
+            "--regress" => opts.regress = true,
+            "--bless" => opts.bless = true,
+            "--frontend" | "--palette" | "--scale" | "--quirks" | "--ipf" |
+            "--hz" | "--timer-hz" | "--load-addr" | "--seed" | "--frames" |
+            "--load-state" | "--rewind" | "--input" | "--dump" |
+            "--record" | "--vip" | "--vip-monitor" | "--crash-report" => {
+                let val = match inline.or_else(|| args.next().cloned()) {
+                    Some(val) => val,
+                    None => return Err(format!("{} expects a value", flag)),
+                };
+
+                match flag {
+                    "--frontend" => opts.frontend = val,
+                    "--palette" => opts.palette = parse_palette(&val)?,
+                    "--scale" => {
+                        // any bigger, and a hires screen is too big to send
+                        opts.scale = parse_num(flag, &val, 32)? as usize
+                    }
+                    "--quirks" => {
+                        quirks = match Quirks::from_name(&val) {
+                            Some(quirks) => Some(quirks),
+                            None => {
+                                return Err(format!(
+                                    "unknown quirks preset '{}', expected \
+                                     one of: {}",
+                                    val,
+                                    quirks::PRESETS.join(", ")))
+                            }
+                        }
+                    }
+                    "--ipf" => {
+                        opts.ipf = parse_num(flag, &val, u32::MAX as u64)?
+                            as u32
+                    }
+                    "--hz" => {
+                        hz = Some(parse_num(flag, &val, u32::MAX as u64)?
+                            as u32)
+                    }
+                    "--timer-hz" => {
+                        opts.timer_hz =
+                            parse_num(flag, &val, u32::MAX as u64)? as u32
+                    }
+                    "--load-addr" => {
+                        opts.load_addr =
+                            parse_num(flag, &val, u16::MAX as u64)? as u16
+                    }
+                    "--seed" => {
+                        opts.seed = Some(parse_num(flag, &val, u64::MAX)?)
+                    }
+                    "--frames" => {
+                        opts.frames = Some(parse_num(flag, &val, u64::MAX)?)
+                    }
+                    "--load-state" => opts.load_state = Some(val),
+                    "--input" => opts.input_script = Some(val),
+                    "--record" => opts.record = Some(val),
+                    "--dump" => opts.dump = Some(val),
+                    "--crash-report" => opts.crash_report = Some(val),
+                    "--vip" => opts.vip_interpreter = Some(val),
+                    "--vip-monitor" => opts.vip_monitor = Some(val),
+                    "--rewind" => {
+                        opts.rewind_secs =
+                            parse_num(flag, &val, u32::MAX as u64)? as u32
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    opts.rom = match rom {
+        Some(rom) => rom,
+        None if opts.conformance || opts.regress => "roms".to_string(),
+        None => return Err("no ROM file given".to_string()),
+    };
+
+    if opts.timer_hz == 0 {
+        return Err("--timer-hz must be greater than 0".to_string());
+    }
+
+    if opts.ipf == 0 {
+        return Err("--ipf must be greater than 0".to_string());
+    }
+
+    // (in frames, it needs to fit in a u32)
+    if opts.rewind_secs.checked_mul(opts.timer_hz).is_none() {
+        return Err("--rewind is too long".to_string());
+    }
+
+    if opts.frames == Some(0) {
+        return Err("--frames must be greater than 0".to_string());
+    }
+
+    // movies start from power-on
+    if opts.record.is_some() && opts.load_state.is_some() {
+        return Err("--record can't be used with --load-state".to_string());
+    }
+
+    if opts.scale == 0 {
+        return Err("--scale must be greater than 0".to_string());
+    }
+
+    if opts.headless {
+        if opts.frames.is_none() {
+            return Err("--headless needs --frames".to_string());
+        }
+        opts.frontend = "null".to_string();
+        opts.dump = opts.dump.or_else(|| Some("-".to_string()));
+    }
+
+    if let Some(hz) = hz {
+        opts.ipf = (hz / opts.timer_hz).max(1);
+    }
+
+    opts.quirks = match quirks {
+        Some(quirks) => quirks,
+        None if opts.xo_chip => Quirks::xo_chip(),
+        None => Quirks::default(),
+    };
+    opts.quirks.stack_wrap = stack_wrap;
+
+    Ok(Some(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_palette;
+
+    #[test]
+    fn palette() {
+        assert_eq!(parse_palette("000000,ffffff,AaBbCc,#123456"),
+                   Ok([[0x00, 0x00, 0x00],
+                       [0xFF, 0xFF, 0xFF],
+                       [0xAA, 0xBB, 0xCC],
+                       [0x12, 0x34, 0x56]]));
+        assert_eq!(parse_palette(" 000000, 111111 ,222222,333333").unwrap()[1],
+                   [0x11, 0x11, 0x11]);
+    }
+
+    #[test]
+    fn bad_palettes() {
+        for val in &["",
+                     "000000,ffffff,aaaaaa",
+                     "000000,ffffff,aaaaaa,555555,000000",
+                     "000000,ffffff,aaaaaa,55555",
+                     "000000,ffffff,aaaaaa,5555555",
+                     "000000,ffffff,aaaaaa,55555g",
+                     "000000,ffffff,aaaaaa,+55555"] {
+            assert!(parse_palette(val).is_err(), "{}", val);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+// Keeps emulated frames in step with the wall clock.
+//
+// Every frame has a deadline, a fixed frame time after the previous one, so
+// the time spent running / rendering a frame doesn't slow the timers down.
+//
+// If the host falls behind, frames are still emulated, but not presented,
+// until it catches back up. If it falls too far behind (e.g. after sitting
+// in the debugger, or waiting for a key), the missed frames are dropped.
+
+// Give up on catching up after falling this many frames behind
+const MAX_LAG: u32 = 6;
+// Present at least one in this many frames, even when behind
+const MAX_SKIP: u32 = 5;
+
+pub struct Clock {
+    frame_time: Duration,
+    next: Instant, // when the next frame starts
+    skipped: u32,  // frames skipped in a row
+}
+
+impl Clock {
+    pub fn new(hz: u32) -> Clock {
+        Clock {
+            frame_time: Duration::from_secs(1) / hz,
+            next: Instant::now(),
+            skipped: 0,
+        }
+    }
+
+    // Waits for the next frame to start. Returns false if the host is running
+    // behind, and the frame shouldn't be presented.
+    pub fn wait(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            thread::sleep(self.next - now);
+        } else if now - self.next > self.frame_time * MAX_LAG {
+            self.next = now;
+        }
+        self.next += self.frame_time;
+
+        let behind = Instant::now() > self.next;
+        if behind && self.skipped < MAX_SKIP {
+            self.skipped += 1;
+            return false;
+        }
+
+        self.skipped = 0;
+        true
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::Read;
+
+use cpu;
+use cpu::CPU;
+use display::ScreenRAM;
+use frontend::Frontend;
+use input::Input;
+use input::NullInput;
+use quirks::Quirks;
+use ram::RAM;
+use screenshot;
+
+// Conformance tests.
+//
+// Runs the test ROMs that ship in roms/ headless, and reads their verdict
+// off the screen. Each test knows what its "pass" screen looks like, and
+// what label it draws next to an error number. Error numbers are drawn with
+// the built-in 4x5 font, so they can be read back by matching each digit of
+// FONTSET against the screen.
+//
+// Patterns are drawn with 'X' for lit pixels, and ' ' for unlit ones.
+
+struct Test {
+    name: &'static str,
+    file: &'static str,
+    quirks: fn() -> Quirks,
+    frames: u32,
+
+    pass: &'static [&'static str],
+    error: &'static [&'static str],
+}
+
+// SCTEST expects SCHIP 1.1 behavior, and VF to be set when I overflows
+fn sctest_quirks() -> Quirks {
+    Quirks {
+        i_overflow_vf: true,
+        ..Quirks::schip11()
+    }
+}
+
+#[rustfmt::skip]
+const TESTS: [Test; 2] = [
+    Test {
+        name: "SCTEST",
+        file: "SCTEST",
+        quirks: sctest_quirks,
+        frames: 300,
+
+        // "OK"
+        pass: &["XXXX X  X",
+                "X  X X X",
+                "X  X XX",
+                "X  X X X",
+                "XXXX X  X"],
+        // "ERROR", followed by a 3 digit number
+        error: &["XXXX XXX   XXX   XXXX XXX",
+                 "X    X  X  X  X  X  X X  X",
+                 "XXXX XXX   XXX   X  X XXX",
+                 "X    X  X  X  X  X  X X  X",
+                 "XXXX X   X X   X XXXX X"],
+    },
+    Test {
+        name: "BC_test",
+        file: "bc_test.ch8",
+        quirks: Quirks::default,
+        frames: 300,
+
+        // "BON"
+        pass: &["XXXX     XXXX   X    X",
+                "X   X   X    X  XX   X",
+                "X   X   X    X  X X  X",
+                "XXXX    X    X  X  X X",
+                "X   X   X    X  X   XX",
+                "X   X   X    X  X    X",
+                "X   X   X    X  X    X",
+                "XXXX     XXXX   X    X"],
+        // a big "E", followed by a 2 digit number
+        error: &["XXXXXXXX",
+                 "XXXX",
+                 "XXXX",
+                 "XXXXXXXX",
+                 "XXXX",
+                 "XXXX",
+                 "XXXX",
+                 "XXXXXXXX"],
+    },
+];
+
+enum Verdict {
+    Pass,
+    Error(u32),
+    Unknown,
+}
+
+/* ----------  Pattern Matching  ---------- */
+
+fn matches(screen: &ScreenRAM, pattern: &[&str], x: usize, y: usize) -> bool {
+    let width = pattern.iter().map(|row| row.len()).max().unwrap_or(0);
+    if x + width > screen.width() || y + pattern.len() > screen.height() {
+        return false;
+    }
+
+    pattern.iter().enumerate().all(|(dy, row)| {
+        let row = row.as_bytes();
+        (0..width).all(|dx| {
+            let lit = row.get(dx) == Some(&b'X');
+            lit == (screen.pixel(x + dx, y + dy) != 0)
+        })
+    })
+}
+
+// Top-left corner of the first place the pattern shows up on screen
+fn find(screen: &ScreenRAM, pattern: &[&str]) -> Option<(usize, usize)> {
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            if matches(screen, pattern, x, y) {
+                return Some((x, y));
+            }
+        }
+    }
+    None
+}
+
+// The built-in font, as patterns
+fn digit_pattern(digit: usize) -> Vec<String> {
+    cpu::FONTSET[digit * 5..digit * 5 + 5]
+        .iter()
+        .map(|byte| {
+            (0..4)
+                .map(|bit| if byte & (0x80 >> bit) != 0 { 'X' } else { ' ' })
+                .collect()
+        })
+        .collect()
+}
+
+// Reads the number written with the built-in font to the right of `x`,
+// somewhere within rows y..y + height
+fn read_number(screen: &ScreenRAM,
+               x: usize,
+               y: usize,
+               height: usize)
+               -> Option<u32> {
+    let digits = (0..10).map(digit_pattern).collect::<Vec<Vec<String>>>();
+
+    for y in y..(y + height).saturating_sub(4) {
+        let mut number = None;
+        let mut x = x;
+        while x < screen.width() {
+            let digit = (0..10).find(|&digit| {
+                let pattern = digits[digit]
+                    .iter()
+                    .map(|row| row.as_str())
+                    .collect::<Vec<&str>>();
+                matches(screen, &pattern, x, y)
+            });
+
+            match digit {
+                Some(digit) => {
+                    number = Some(number.unwrap_or(0) * 10 + digit as u32);
+                    x += 4;
+                }
+                None => x += 1,
+            }
+        }
+
+        if number.is_some() {
+            return number;
+        }
+    }
+
+    None
+}
+
+fn judge(test: &Test, screen: &ScreenRAM) -> Verdict {
+    if find(screen, test.pass).is_some() {
+        return Verdict::Pass;
+    }
+
+    let (x, y) = match find(screen, test.error) {
+        Some(pos) => pos,
+        None => return Verdict::Unknown,
+    };
+
+    let width = test.error.iter().map(|row| row.len()).max().unwrap_or(0);
+    match read_number(screen, x + width, y, test.error.len()) {
+        Some(number) => Verdict::Error(number),
+        None => Verdict::Unknown,
+    }
+}
+
+/* ----------  Runner  ---------- */
+
+// Runs a ROM headless for a number of frames, and hands back the CPU so its
+// final state can be checked. Also used by the regression suite.
+pub fn run_rom(path: &str,
+               quirks: Quirks,
+               input: Box<dyn Input>,
+               seed: u64,
+               frames: u32)
+               -> Result<CPU, String> {
+    let mut rom = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut rom))
+        .map_err(|e| format!("couldn't read '{}': {}", path, e))?;
+
+    let mut ram = RAM::new();
+    for (i, byte) in rom.into_iter().enumerate() {
+        ram.store_u8(0x200 + i as u16, byte).map_err(|e| e.to_string())?;
+    }
+
+    let mut frontend = Frontend::null();
+    frontend.input = input;
+    let mut cpu = CPU::new(ram, frontend, quirks, 0x200, seed);
+
+    for _ in 0..frames {
+        for _ in 0..10 {
+            cpu.frontend().input.update_keys(false);
+            let state = cpu.cycle().map_err(|e| e.to_string())?;
+            if let cpu::CPUState::Exit = state {
+                return Ok(cpu);
+            }
+        }
+        cpu.decrement_counters();
+        cpu.frontend().input.decrement_keys();
+    }
+
+    Ok(cpu)
+}
+
+// Runs every test ROM in `dir`. Returns true if they all passed.
+pub fn run_all(dir: &str) -> bool {
+    let mut passed = true;
+
+    for test in TESTS.iter() {
+        let path = format!("{}/{}", dir, test.file);
+        let input = Box::new(NullInput::new());
+        let cpu = match run_rom(&path, (test.quirks)(), input, 0, test.frames) {
+            Ok(cpu) => cpu,
+            Err(why) => {
+                println!("{:<8} FAIL ({})", test.name, why);
+                passed = false;
+                continue;
+            }
+        };
+
+        match judge(test, cpu.screen()) {
+            Verdict::Pass => println!("{:<8} pass", test.name),
+            Verdict::Error(number) => {
+                println!("{:<8} FAIL (error {}, see {}.txt)",
+                         test.name,
+                         number,
+                         path.trim_end_matches(".ch8"));
+                passed = false;
+            }
+            Verdict::Unknown => {
+                println!("{:<8} FAIL (unrecognized screen)", test.name);
+                print!("{}", screenshot::text(cpu.screen()));
+                passed = false;
+            }
+        }
+    }
+
+    passed
+}
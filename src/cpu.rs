@@ -1,40 +1,64 @@
-extern crate rand;
-
+use audio;
 use display;
+use display::Resolution;
+use display::Update;
+use error::EmuError;
+use frontend::Frontend;
 use input;
+use quirks::LoadStore;
+use quirks::Quirks;
 use ram;
+use rng::Rng;
+use savestate;
+use stack::Stack;
 use types::Chip8Utils;
 
 #[derive(PartialEq)]
 pub enum CPUState {
     Running,
     WaitForInput,
+    Exit,
 }
 
-pub struct CPU<'a, Dt: 'a + display::Update, It: 'a + input::Get> {
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU {
     cycle: u32,
 
     v: [u8; 16],
     i: u16,
 
     pc: u16,
-    stack: Vec<u16>,
+    stack: Stack,
 
     dt: u8,
     st: u8,
 
-    ram: &'a mut ram::RAM,
-    display: &'a Dt,
-    input: &'a It,
+    // SCHIP "RPL user flags" (HP-48 persistent registers)
+    // SCHIP only has 8 of these, XO-CHIP extends them to 16
+    rpl: [u8; 16],
+
+    // XO-CHIP audio pattern buffer and pitch
+    audio: audio::Audio,
+
+    quirks: Quirks,
+    rng: Rng,
+
+    ram: ram::RAM,
+    screen: display::ScreenRAM,
+    frontend: Frontend,
 }
 
-impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
-    pub fn new(ram: &'a mut ram::RAM,
-               display: &'a Dt,
-               input: &'a It)
-               -> CPU<'a, Dt, It> {
-        // Load FONTSET into RAM
-        for (i, byte) in FONTSET.iter().enumerate() {
+impl CPU {
+    // `entry` is where execution starts (usually 0x200), and `seed` seeds
+    // the random number generator used by Cxkk.
+    pub fn new(mut ram: ram::RAM,
+               frontend: Frontend,
+               quirks: Quirks,
+               entry: u16,
+               seed: u64)
+               -> CPU {
+        // Load FONTSET and BIG_FONTSET into RAM
+        for (i, byte) in FONTSET.iter().chain(BIG_FONTSET.iter()).enumerate() {
             // i'm just going to unwrap this value, since I know it won't fail.
             ram.store_u8(i as u16, *byte).unwrap();
         }
@@ -45,18 +69,142 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
             v: [0; 16],
             i: 0,
 
-            pc: 0x200,
-            stack: vec![],
+            pc: entry,
+            stack: Stack::new(quirks.stack_depth),
 
             dt: 0,
             st: 0,
 
-            ram: ram,
-            display: display,
-            input: input,
+            rpl: [0; 16],
+
+            audio: audio::Audio::new(),
+
+            quirks,
+            rng: Rng::new(seed),
+
+            ram,
+            screen: display::ScreenRAM::new(),
+            frontend,
         }
     }
 
+    pub fn frontend(&self) -> &Frontend {
+        &self.frontend
+    }
+
+    pub fn screen(&self) -> &display::ScreenRAM {
+        &self.screen
+    }
+
+    pub fn audio(&self) -> &audio::Audio {
+        &self.audio
+    }
+
+    pub fn ram(&self) -> &ram::RAM {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut ram::RAM {
+        &mut self.ram
+    }
+
+    /* ----------  Save States  ---------- */
+
+    // Note that quirks are not part of the machine state
+    pub fn save_state(&self, w: &mut savestate::Writer) {
+        w.u32(self.cycle);
+
+        w.bytes(&self.v);
+        w.u16(self.i);
+
+        w.u16(self.pc);
+        self.stack.save_state(w);
+
+        w.u8(self.dt);
+        w.u8(self.st);
+
+        w.bytes(&self.rpl);
+        w.bytes(&self.audio.pattern);
+        w.u8(self.audio.pitch);
+
+        self.rng.save_state(w);
+        self.ram.save_state(w);
+        self.screen.save_state(w);
+        self.frontend.input.latch().save_state(w);
+    }
+
+    // Everything is read before any of it is restored, so a bad save state
+    // leaves the machine as it was
+    pub fn load_state(&mut self, r: &mut savestate::Reader)
+                      -> Result<(), String> {
+        let cycle = r.u32()?;
+
+        let mut v = [0; 16];
+        r.array(&mut v)?;
+        let i = r.u16()?;
+
+        let pc = r.u16()?;
+        let stack = Stack::load_state(r, self.quirks.stack_depth)?;
+
+        let dt = r.u8()?;
+        let st = r.u8()?;
+
+        let mut rpl = [0; 16];
+        r.array(&mut rpl)?;
+        let mut pattern = [0; 16];
+        r.array(&mut pattern)?;
+        let pitch = r.u8()?;
+
+        let rng = Rng::load_state(r)?;
+        let mem = self.ram.load_state(r)?;
+        let screen = display::ScreenRAM::load_state(r)?;
+        let latch = input::Latch::load_state(r)?;
+
+        self.cycle = cycle;
+        self.v = v;
+        self.i = i;
+        self.pc = pc;
+        self.stack = stack;
+        self.dt = dt;
+        self.st = st;
+        self.rpl = rpl;
+        self.audio.pattern = pattern;
+        self.audio.pitch = pitch;
+        self.rng = rng;
+        self.ram.restore(mem);
+        self.screen = screen;
+        self.frontend.input.restore_latch(&latch);
+
+        Ok(())
+    }
+
+    /* ----------  Inspection (for the debugger)  ---------- */
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    // The return addresses on the stack, oldest first
+    pub fn stack(&self) -> Vec<u16> {
+        self.stack.entries()
+    }
+
+    pub fn timers(&self) -> (u8, u8) {
+        (self.dt, self.st)
+    }
+
+    pub fn cycles(&self) -> u32 {
+        self.cycle
+    }
+
     pub fn decrement_counters(&mut self) {
         self.dt -= if self.dt > 0 { 1 } else { 0 };
         self.st -= if self.st > 0 { 1 } else { 0 };
@@ -66,12 +214,57 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
         self.st != 0
     }
 
-    pub fn cycle(&mut self) -> Result<CPUState, String> {
+    // (quirk) Logical operations reset VF
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+    }
+
+    // (quirk) Fx55 / Fx65 modify I
+    fn increment_i(&mut self, x: usize) {
+        self.i = self.i.wrapping_add(match self.quirks.load_store {
+            LoadStore::IncrementByXPlusOne => x as u16 + 1,
+            LoadStore::IncrementByX => x as u16,
+            LoadStore::Unchanged => 0,
+        });
+    }
+
+    // (quirk) Mirror the stack into RAM, where the VIP keeps it
+    fn store_stack(&mut self) -> Result<(), EmuError> {
+        if !self.quirks.stack_in_ram {
+            return Ok(());
+        }
+
+        for (depth, addr) in self.stack.entries().iter().enumerate() {
+            self.ram.store_u16(stack_slot(depth), *addr)?;
+        }
+        Ok(())
+    }
+
+    // Skip the next instruction.
+    // XO-CHIP's `F000 nnnn` is 4 bytes long, so it has to be skipped over
+    // entirely.
+    // (PC wraps around past 0xFFFF, the end of XO-CHIP's 64 KiB of RAM)
+    fn skip(&mut self) -> Result<(), EmuError> {
+        let len = if self.ram.load_u16(self.pc)? == 0xF000 { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(len);
+        Ok(())
+    }
+
+    pub fn cycle(&mut self) -> Result<CPUState, EmuError> {
+        let pc = self.pc;
         self.cycle += 1;
 
         // Load instr from RAM
-        let instr = self.ram.load_u16(self.pc)?;
-        self.pc += 2;
+        let instr = self.ram.load_u16(pc).map_err(|e| e.at(pc, None))?;
+        self.pc = self.pc.wrapping_add(2);
+
+        self.execute(pc, instr).map_err(|e| e.at(pc, Some(instr)))
+    }
+
+    fn execute(&mut self, pc: u16, instr: u16)
+               -> Result<CPUState, EmuError> {
 
         // these values aren't used in *every* instruction, but they are nice
         // to have on hand. It helps keep the code clean :)
@@ -79,29 +272,85 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
         let y = instr.nibble_at(2) as usize;
         let nnn = instr & 0x0FFF;
         let kk = (instr & 0x00FF) as u8;
+        let res = self.screen.resolution();
 
         match instr.nibble_at(0) {
+            // 00Cn - SCD nibble (SCHIP)
+            // Scroll the display down by n lines.
+            0x0 if nnn & 0xFF0 == 0x0C0 => {
+                self.screen.scroll_down(instr.nibble_at(3))
+            }
+            // 00Dn - SCU nibble (XO-CHIP)
+            // Scroll the display up by n lines.
+            0x0 if nnn & 0xFF0 == 0x0D0 => {
+                self.screen.scroll_up(instr.nibble_at(3))
+            }
             // 00E0 - CLS
             // Clear the display.
-            0x0 if nnn == 0x0E0 => self.display.clear(),
+            0x0 if nnn == 0x0E0 => self.screen.clear(),
+            // 0230 - CLS (HIRES CHIP-8)
+            // Clear the 64x64 display.
+            0x0 if nnn == 0x230 && res == Resolution::Tall => {
+                self.screen.clear()
+            }
             // 00EE - RET
             // Return from a subroutine.
             // The interpreter sets the program counter to the address at
             // the top of the stack, then subtracts 1 from the stack pointer
             0x0 if nnn == 0x0EE => {
                 // I'm assuming you can't RET when the stack is clear...
-                self.pc = match self.stack.pop() {
+                let addr = match self.stack.pop() {
                     Some(addr) => addr,
-                    None => return Err("[CPU] Cannot RET when stack is empty!"
-                                           .to_string()),
+                    None => {
+                        return Err(EmuError::StackUnderflow {
+                            pc: Some(pc),
+                            opcode: Some(instr),
+                        })
+                    }
+                };
+                // (quirk) The ROM may have changed the stack in RAM
+                self.pc = if self.quirks.stack_in_ram {
+                    self.ram.load_u16(stack_slot(self.stack.len()))?
+                } else {
+                    addr
                 };
             }
+            // 00FB - SCR (SCHIP)
+            // Scroll the display right by 4 pixels.
+            0x0 if nnn == 0x0FB => self.screen.scroll_right(),
+            // 00FC - SCL (SCHIP)
+            // Scroll the display left by 4 pixels.
+            0x0 if nnn == 0x0FC => self.screen.scroll_left(),
+            // 00FD - EXIT (SCHIP)
+            // Exit the interpreter.
+            0x0 if nnn == 0x0FD => {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(CPUState::Exit);
+            }
+            // 00FE - LOW (SCHIP)
+            // Disable extended screen mode (64x32).
+            0x0 if nnn == 0x0FE => {
+                self.screen.set_resolution(Resolution::Low)
+            }
+            // 00FF - HIGH (SCHIP)
+            // Enable extended screen mode (128x64).
+            0x0 if nnn == 0x0FF => {
+                self.screen.set_resolution(Resolution::High)
+            }
             // 0nnn - SYS addr
             // Jump to a machine code routine at nnn.
             // This instruction is only used on the old computers on which
             // Chip-8 was originally implemented. It is ignored by modern
             // interpreters.
             0x0 => (),
+            // 1260 - HIRES CHIP-8 entry point
+            // HIRES CHIP-8 programs start with a jump over a patched copy of
+            // the interpreter. Instead of running it, switch to 64x64 mode
+            // and jump straight to the program proper.
+            0x1 if self.pc == 0x202 && nnn == 0x260 => {
+                self.screen.set_resolution(Resolution::Tall);
+                self.pc = 0x2C0;
+            }
             // 1nnn - JP addr
             // Jump to location nnn.
             // The interpreter sets the program counter to nnn.
@@ -110,25 +359,68 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
             // Call subroutine at nnn.
             // The interpreter increments the stack pointer, then puts the
             // current PC on the top of the stack. The PC is then set to nnn
+            // (quirk) A full stack loses its oldest return address instead
+            // of overflowing.
             0x2 => {
+                if self.stack.is_full() && !self.quirks.stack_wrap {
+                    return Err(EmuError::StackOverflow {
+                        pc: Some(pc),
+                        opcode: Some(instr),
+                    });
+                }
                 self.stack.push(self.pc);
+                self.store_stack()?;
                 self.pc = nnn;
             }
             // 3xkk - SE Vx, byte
             // Skip next instruction if Vx = kk.
             // The interpreter compares register Vx to kk, and if they are
             // equal, increments the program counter by 2.
-            0x3 => self.pc += if self.v[x] == kk { 2 } else { 0 },
+            0x3 if self.v[x] == kk => self.skip()?,
+            0x3 => (),
             // 4xkk - SNE Vx, byte
             // Skip next instruction if Vx != kk.
             // The interpreter compares register Vx to kk, and if they are
             // not equal, increments the program counter by 2.
-            0x4 => self.pc += if self.v[x] != kk { 2 } else { 0 },
+            0x4 if self.v[x] != kk => self.skip()?,
+            0x4 => (),
             // 5xy0 - SE Vx, Vy
             // Skip next instruction if Vx = Vy.
             // The interpreter compares register Vx to register Vy, and if
             // they are equal, increments the program counter by 2.
-            0x5 => self.pc += if self.v[x] == self.v[y] { 2 } else { 0 },
+            0x5 => match instr.nibble_at(3) {
+                0x0 if self.v[x] == self.v[y] => self.skip()?,
+                0x0 => (),
+                // 5xy2 - LD [I], Vx-Vy (XO-CHIP)
+                // Store registers Vx through Vy in memory starting at
+                // location I. I is not modified. If x > y, the registers
+                // are stored in reverse order.
+                0x2 => {
+                    for (offset, reg) in register_range(x, y)
+                        .into_iter()
+                        .enumerate() {
+                        let addr = self.i.wrapping_add(offset as u16);
+                        self.ram.store_u8(addr, self.v[reg])?;
+                    }
+                }
+                // 5xy3 - LD Vx-Vy, [I] (XO-CHIP)
+                // Read registers Vx through Vy from memory starting at
+                // location I. I is not modified.
+                0x3 => {
+                    for (offset, reg) in register_range(x, y)
+                        .into_iter()
+                        .enumerate() {
+                        let addr = self.i.wrapping_add(offset as u16);
+                        self.v[reg] = self.ram.load_u8(addr)?;
+                    }
+                }
+                _ => {
+                    return Err(EmuError::InvalidOpcode {
+                        pc: Some(pc),
+                        opcode: instr,
+                    })
+                }
+            },
             // 6xkk - LD Vx, byte
             // Set Vx = kk.
             // The interpreter puts the value kk into register Vx.
@@ -147,17 +439,29 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
                 // Set Vx = Vx OR Vy.
                 // Performs a bitwise OR on the values of Vx and Vy,
                 // then stores the result in Vx.
-                0x1 => self.v[x] = self.v[x] | self.v[y],
+                // (quirk) VF is reset to 0.
+                0x1 => {
+                    self.v[x] |= self.v[y];
+                    self.reset_vf();
+                }
                 // 8xy2 - AND Vx, Vy
                 // Set Vx = Vx AND Vy.
                 // Performs a bitwise AND on the values of Vx and Vy,
                 // then stores the result in Vx.
-                0x2 => self.v[x] = self.v[x] & self.v[y],
+                // (quirk) VF is reset to 0.
+                0x2 => {
+                    self.v[x] &= self.v[y];
+                    self.reset_vf();
+                }
                 // 8xy3 - XOR Vx, Vy
                 // Set Vx = Vx XOR Vy.
                 // Performs a bitwise exclusive OR on the values of Vx
                 // and Vy, then stores the result in Vx.
-                0x3 => self.v[x] = self.v[x] ^ self.v[y],
+                // (quirk) VF is reset to 0.
+                0x3 => {
+                    self.v[x] ^= self.v[y];
+                    self.reset_vf();
+                }
                 // 8xy4 - ADD Vx, Vy
                 // Set Vx = Vx + Vy, set VF = carry.
                 // The values of Vx and Vy are added together.
@@ -181,9 +485,15 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
                 // Set Vx = Vx SHR 1.
                 // If the least-significant bit of Vx is 1, then VF is
                 // set to 1, otherwise 0. Then Vx is divided by 2.
+                // (quirk) Vx = Vy SHR 1.
                 0x6 => {
-                    self.v[0xF] = self.v[x] & 0x01;
-                    self.v[x] >>= 1;
+                    let val = if self.quirks.shift_vy {
+                        self.v[y]
+                    } else {
+                        self.v[x]
+                    };
+                    self.v[x] = val >> 1;
+                    self.v[0xF] = val & 0x01;
                 }
                 // 8xy7 - SUBN Vx, Vy
                 // Set Vx = Vy - Vx, set VF = NOT borrow.
@@ -197,18 +507,29 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
                 // Set Vx = Vx SHL 1.
                 // If the most-significant bit of Vx is 1, then VF is
                 // set to 1, otherwise to 0. Then Vx is multiplied by 2.
+                // (quirk) Vx = Vy SHL 1.
                 0xE => {
-                    self.v[0xF] = self.v[x] >> 7;
-                    self.v[x] <<= 1;
+                    let val = if self.quirks.shift_vy {
+                        self.v[y]
+                    } else {
+                        self.v[x]
+                    };
+                    self.v[x] = val << 1;
+                    self.v[0xF] = val >> 7;
+                }
+                _ => {
+                    return Err(EmuError::InvalidOpcode {
+                        pc: Some(pc),
+                        opcode: instr,
+                    })
                 }
-                _ => return Err(format!("[CPU] Invalid Opcode - {:04x}",
-                                        instr)),
             },
             // 9xy0 - SNE Vx, Vy
             // Skip next instruction if Vx != Vy.
             // The values of Vx and Vy are compared, and if they are not
             // equal, the program counter is increased by 2.
-            0x9 => self.pc += if self.v[x] != self.v[y] { 2 } else { 0 },
+            0x9 if self.v[x] != self.v[y] => self.skip()?,
+            0x9 => (),
             // Annn - LD I, addr
             // Set I = nnn.
             // The value of register I is set to nnn.
@@ -216,13 +537,16 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
             // Bnnn - JP V0, addr
             // Jump to location nnn + V0.
             // The program counter is set to nnn plus the value of V0.
+            // (quirk) Bxnn - JP Vx, addr
+            // The program counter is set to xnn plus the value of Vx.
+            0xB if self.quirks.jump_vx => self.pc = nnn + self.v[x] as u16,
             0xB => self.pc = nnn + self.v[0] as u16,
             // Cxkk - RND Vx, byte
             // Set Vx = random byte AND kk.
             // The interpreter generates a random number from 0 to 255,
             // which is then ANDed with the value kk. The results are stored
             // in Vx. See instruction 8xy2 for more information on AND.
-            0xC => self.v[x] = rand::random::<u8>() & kk,
+            0xC => self.v[x] = self.rng.next_u8() & kk,
             // Dxyn - DRW Vx, Vy, nibble
             // Display n-byte sprite starting at memory location I at
             // (Vx, Vy), set VF = collision.
@@ -236,40 +560,92 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
             // If the sprite is positioned so part of it is outside the
             // coordinates of the display, it wraps around to the opposite side
             // of the screen.
+            // (quirk) Sprites are clipped at the edges of the screen instead.
+            // Dxy0 - DRW Vx, Vy, 0 (SCHIP)
+            // In extended screen mode, draws a 16x16 sprite (32 bytes). In
+            // normal mode, draws an 8x16 sprite.
+            // (quirk) Draws a 16x16 sprite in normal mode too.
+            // With XO-CHIP, the sprite is drawn once per selected bitplane,
+            // with each plane's data following the previous one's in memory.
             0xD => {
-                // check for unexpected overflows of the I register
-                if (self.i + instr.nibble_at(3) as u16) > 0xFFF {
-                    return Err("[CPU] Unexpected Overflow".to_string());
+                let n = instr.nibble_at(3) as u16;
+                let len = match n {
+                    0 if res == Resolution::High => 32,
+                    0 if self.quirks.lores_wide_sprites => 32,
+                    0 => 16,
+                    n => n,
+                };
+                let planes = self.screen.planes().count_ones() as u16;
+
+                // check for unexpected overflows of the I register (in
+                // usize, as I can be right up against 0xFFFF with XO-CHIP)
+                let end = self.i as usize + (len * planes) as usize;
+                if end > self.ram.size() {
+                    return Err(EmuError::IOverflow {
+                        pc: Some(pc),
+                        opcode: Some(instr),
+                        addr: self.i,
+                    });
                 }
 
-                let sprite = (self.i..(self.i + instr.nibble_at(3) as u16))
-                    .map(|addr| self.ram.load_u8(addr))
+                let sprite = (self.i as usize..end)
+                    .map(|addr| self.ram.load_u8(addr as u16))
                     .collect::<Result<Vec<u8>, _>>()?;
 
-                self.v[0xF] =
-                    self.display.draw(self.v[x], self.v[y], &sprite) as u8;
+                let (vx, vy) = (self.v[x], self.v[y]);
+                let clip = self.quirks.clip_sprites;
+                self.v[0xF] = if len == 32 {
+                    self.screen.draw_wide(vx, vy, &sprite, clip)
+                } else {
+                    self.screen.draw(vx, vy, &sprite, clip)
+                } as u8;
             }
             // Ex9E - SKP Vx
             // Skip next instruction if key with the value of Vx is pressed.
             // Checks the keyboard, and if the key corresponding to the
             // value of Vx is currently in the down position, PC is
             // increased by 2.
-            0xE if kk == 0x9E => {
-                if self.input.pressed_key(self.v[x]) {
-                    self.pc += 2;
-                }
+            0xE if kk == 0x9E &&
+                   self.frontend.input.pressed_key(self.v[x]) => {
+                self.skip()?
             }
+            0xE if kk == 0x9E => (),
             // ExA1 - SKNP Vx
             // Skip next instruction if key with the value of Vx is not pressed.
             // Checks the keyboard, and if the key corresponding to the
             // value of Vx is currently in the up position, PC is increased
             // by 2.
-            0xE if kk == 0xA1 => {
-                if !self.input.pressed_key(self.v[x]) {
-                    self.pc += 2;
+            0xE if kk == 0xA1 &&
+                   !self.frontend.input.pressed_key(self.v[x]) => {
+                self.skip()?
+            }
+            0xE if kk == 0xA1 => (),
+            0xE => {
+                return Err(EmuError::InvalidOpcode {
+                        pc: Some(pc),
+                        opcode: instr,
+                    })
+            }
+            // F000 nnnn - LD I, long (XO-CHIP)
+            // Set I = nnnn.
+            // The 16-bit address is read from the word following the
+            // instruction.
+            0xF if nnn == 0x000 => {
+                self.i = self.ram.load_u16(self.pc)?;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            // Fn01 - PLANE n (XO-CHIP)
+            // Select the bitplanes that are drawn to / cleared / scrolled.
+            0xF if kk == 0x01 => self.screen.set_planes(x as u8),
+            // F002 - AUDIO (XO-CHIP)
+            // Load the 16-byte audio pattern buffer from memory starting at
+            // location I.
+            0xF if nnn == 0x002 => {
+                for offset in 0..self.audio.pattern.len() {
+                    let addr = self.i.wrapping_add(offset as u16);
+                    self.audio.pattern[offset] = self.ram.load_u8(addr)?;
                 }
             }
-            0xE => return Err(format!("[CPU] Invalid Opcode - {:04x}", instr)),
             0xF => match kk {
                 // Fx07 - LD Vx, DT
                 // Set Vx = delay timer value.
@@ -283,11 +659,11 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
                 0x0A => {
                     // I hate this instruction.
                     // It makes my life so incredibly difficult...
-                    match self.input.last_press() {
+                    match self.frontend.input.last_press() {
                         Some(key) => self.v[x] = key,
                         None => {
                             self.cycle -= 1;
-                            self.pc -= 2;
+                            self.pc = self.pc.wrapping_sub(2);
                             return Ok(CPUState::WaitForInput);
                         }
                     }
@@ -304,14 +680,43 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
                 // Set I = I + Vx.
                 // The values of I and Vx are added, and the results are
                 // stored in I.
-                0x1E => self.i = self.i + self.v[x] as u16,
+                // (quirk) VF is set to 1 if I overflows past 0xFFF,
+                // otherwise 0.
+                0x1E => {
+                    self.i = self.i.wrapping_add(self.v[x] as u16);
+                    if self.quirks.i_overflow_vf {
+                        self.v[0xF] = (self.i > 0xFFF) as u8;
+                    }
+                }
                 // Fx29 - LD F, Vx
                 // Set I = location of sprite for digit Vx.
                 // The value of I is set to the location for the
                 // hexadecimal sprite corresponding to the value of Vx.
                 0x29 if self.v[x] <= 0xF => self.i = self.v[x] as u16 * 5,
-                0x29 => return Err(format!("[CPU] Invalid Opcode - {:04x}",
-                                           instr)),
+                0x29 => {
+                    return Err(EmuError::BadFontDigit {
+                        pc: Some(pc),
+                        opcode: Some(instr),
+                        digit: self.v[x],
+                    })
+                }
+                // Fx30 - LD HF, Vx (SCHIP)
+                // Set I = location of 10-byte sprite for digit Vx.
+                // The big font is stored directly after the regular font.
+                0x30 if self.v[x] <= 0xF => {
+                    self.i = FONTSET.len() as u16 + self.v[x] as u16 * 10
+                }
+                0x30 => {
+                    return Err(EmuError::BadFontDigit {
+                        pc: Some(pc),
+                        opcode: Some(instr),
+                        digit: self.v[x],
+                    })
+                }
+                // Fx3A - PITCH Vx (XO-CHIP)
+                // Set the audio pattern playback rate to
+                // 4000*2^((Vx-64)/48) Hz.
+                0x3A => self.audio.pitch = self.v[x],
                 // Fx33 - LD B, Vx
                 // Store BCD representation of Vx in memory locations I,
                 // I+1, and I+2.
@@ -319,37 +724,55 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
                 // places the hundreds digit in memory at location in I,
                 // the tens digit at location I+1, and the ones digit at
                 // location I+2.
+                // (I wraps around past 0xFFFF, like with 5xy2 / 5xy3)
                 0x33 => {
-                    self.ram.store_u8(self.i + 0, self.v[x] / 100 % 10)?;
-                    self.ram.store_u8(self.i + 1, self.v[x] / 10 % 10)?;
-                    self.ram.store_u8(self.i + 2, self.v[x] / 1 % 10)?;
+                    let (i, vx) = (self.i, self.v[x]);
+                    self.ram.store_u8(i, vx / 100)?;
+                    self.ram.store_u8(i.wrapping_add(1), vx / 10 % 10)?;
+                    self.ram.store_u8(i.wrapping_add(2), vx % 10)?;
                 }
                 // Fx55 - LD [I], Vx
                 // Store registers V0 through Vx in memory starting at
                 // location I.
                 // The interpreter copies the values of registers V0
                 // through Vx into memory, starting at the address in I.
+                // (quirk) I is incremented by x + 1, or by x.
                 0x55 => {
                     for x in 0..(x + 1) {
-                        self.ram.store_u8(self.i + x as u16, self.v[x])?;
+                        let addr = self.i.wrapping_add(x as u16);
+                        self.ram.store_u8(addr, self.v[x])?;
                     }
+                    self.increment_i(x);
                 }
                 // Fx65 - LD Vx, [I]
                 // Read registers V0 through Vx from memory starting at
                 // location I.
                 // The interpreter reads values from memory starting at
                 // location I into registers V0 through Vx.
+                // (quirk) I is incremented by x + 1, or by x.
                 0x65 => {
                     for x in 0..(x + 1) {
-                        self.v[x] = self.ram.load_u8(self.i + x as u16)?;
+                        let addr = self.i.wrapping_add(x as u16);
+                        self.v[x] = self.ram.load_u8(addr)?;
                     }
+                    self.increment_i(x);
                 }
 
-                0x75 => { /* SCHIP-8 - IGNORED */ }
-                0x85 => { /* SCHIP-8 - IGNORED */ }
+                // Fx75 - LD R, Vx (SCHIP)
+                // Store V0 through Vx in the RPL user flags (x <= 7, or
+                // x <= F with XO-CHIP).
+                0x75 => self.rpl[..=x].copy_from_slice(&self.v[..=x]),
+                // Fx85 - LD Vx, R (SCHIP)
+                // Read V0 through Vx from the RPL user flags (x <= 7, or
+                // x <= F with XO-CHIP).
+                0x85 => self.v[..=x].copy_from_slice(&self.rpl[..=x]),
 
-                _ => return Err(format!("[CPU] Invalid Opcode - {:04x}",
-                                        instr)),
+                _ => {
+                    return Err(EmuError::InvalidOpcode {
+                        pc: Some(pc),
+                        opcode: instr,
+                    })
+                }
             },
             // this will never happen, but Rust doesn't know what a nibble is,
             // so it thinks that there are more cases that need to be checked.
@@ -361,8 +784,23 @@ impl<'a, Dt: display::Update, It: input::Get> CPU<'a, Dt, It> {
     }
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-static FONTSET: [u8; 80] = [
+// Where the return address at `depth` lives, when the stack is in RAM. The
+// VIP pushes them onto a stack growing down from 0xECF, high byte first.
+fn stack_slot(depth: usize) -> u16 {
+    0xECE - 2 * depth as u16
+}
+
+// Registers x through y, in reverse order if x > y (used by 5xy2 / 5xy3)
+fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+#[rustfmt::skip]
+pub static FONTSET: [u8; 80] = [
   /* 0 */ 0xF0, 0x90, 0x90, 0x90, 0xF0,
   /* 1 */ 0x20, 0x60, 0x20, 0x20, 0x70,
   /* 2 */ 0xF0, 0x10, 0xF0, 0x80, 0xF0,
@@ -380,3 +818,312 @@ static FONTSET: [u8; 80] = [
   /* E */ 0xF0, 0x80, 0xF0, 0x80, 0xF0,
   /* F */ 0xF0, 0x80, 0xF0, 0x80, 0x80,
 ];
+
+// SCHIP 10-byte (8x10) font. Digits A-F are not part of SCHIP 1.1, but are
+// included for convenience (XO-CHIP defines them).
+#[rustfmt::skip]
+static BIG_FONTSET: [u8; 160] = [
+  /* 0 */ 0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,
+  /* 1 */ 0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,
+  /* 2 */ 0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,
+  /* 3 */ 0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,
+  /* 4 */ 0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,
+  /* 5 */ 0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,
+  /* 6 */ 0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,
+  /* 7 */ 0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,
+  /* 8 */ 0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,
+  /* 9 */ 0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C,
+  /* A */ 0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+  /* B */ 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+  /* C */ 0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+  /* D */ 0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+  /* E */ 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+  /* F */ 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A CPU with `program` loaded at 0x200, and nothing attached
+    fn load(quirks: Quirks, ram: ram::RAM, program: &[u16]) -> CPU {
+        let mut cpu = CPU::new(ram, Frontend::null(), quirks, 0x200, 1);
+        for (n, instr) in program.iter().enumerate() {
+            cpu.ram.store_u16(0x200 + 2 * n as u16, *instr).unwrap();
+        }
+        cpu
+    }
+
+    fn chip8(program: &[u16]) -> CPU {
+        load(Quirks::default(), ram::RAM::new(), program)
+    }
+
+    fn xo_chip(program: &[u16]) -> CPU {
+        load(Quirks::xo_chip(), ram::RAM::with_size(0x10000), program)
+    }
+
+    // Runs `n` instructions
+    fn run(cpu: &mut CPU, n: usize) {
+        for _ in 0..n {
+            cpu.cycle().unwrap();
+        }
+    }
+
+    // The lit pixels, as (x, y)
+    fn lit(cpu: &CPU) -> Vec<(usize, usize)> {
+        let screen = cpu.screen();
+        let mut lit = vec![];
+        for y in 0..screen.height() {
+            for x in 0..screen.width() {
+                if screen.pixel(x, y) != 0 {
+                    lit.push((x, y));
+                }
+            }
+        }
+        lit
+    }
+
+    /* ----------  SCHIP  ---------- */
+
+    #[test]
+    fn resolution() {
+        let mut cpu = chip8(&[0x00FF, 0x00FE]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.screen().resolution(), Resolution::High);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.screen().resolution(), Resolution::Low);
+    }
+
+    #[test]
+    fn scroll() {
+        let mut cpu = chip8(&[0x00FF, 0x00C3, 0x00FB, 0x00FC, 0x00FC,
+                              0x00FC]);
+        run(&mut cpu, 1);
+        cpu.screen().set_pixel(4, 0, 1);
+
+        run(&mut cpu, 1);
+        assert_eq!(lit(&cpu), vec![(4, 3)]);
+        run(&mut cpu, 1);
+        assert_eq!(lit(&cpu), vec![(8, 3)]);
+        run(&mut cpu, 2);
+        assert_eq!(lit(&cpu), vec![(0, 3)]);
+        run(&mut cpu, 1);
+        assert_eq!(lit(&cpu), vec![]); // scrolled off the left edge
+    }
+
+    #[test]
+    fn exit() {
+        let mut cpu = chip8(&[0x00FD]);
+        assert!(cpu.cycle().unwrap() == CPUState::Exit);
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn big_sprite() {
+        // Dxy0 draws 16x16 in hires, and 8x16 in lores
+        let mut cpu = chip8(&[0x00FF, 0xA300, 0xD000, 0xD000,
+                              0x00FE, 0xD000]);
+        for addr in 0x300..0x320 {
+            cpu.ram.store_u8(addr, 0xFF).unwrap();
+        }
+
+        run(&mut cpu, 3);
+        assert_eq!(lit(&cpu).len(), 16 * 16);
+        assert_eq!(cpu.v[0xF], 0);
+        run(&mut cpu, 1);
+        assert_eq!(lit(&cpu).len(), 0);
+        assert_eq!(cpu.v[0xF], 1);
+
+        run(&mut cpu, 2);
+        assert_eq!(lit(&cpu).len(), 8 * 16);
+    }
+
+    #[test]
+    fn big_font() {
+        let mut cpu = chip8(&[0x6003, 0xF030, 0x6010, 0xF030]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.i, FONTSET.len() as u16 + 3 * 10);
+        assert_eq!(cpu.ram.load_u8(cpu.i).unwrap(), BIG_FONTSET[30]);
+
+        run(&mut cpu, 1);
+        match cpu.cycle() {
+            Err(EmuError::BadFontDigit { digit: 0x10, .. }) => (),
+            other => panic!("expected BadFontDigit, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let mut cpu = chip8(&[0xF275, 0x6000, 0x6100, 0x6200, 0xF185]);
+        cpu.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+        run(&mut cpu, 5);
+        // only V0-V1 read back, V2 stays cleared
+        assert_eq!(cpu.v[..4], [1, 2, 0, 4]);
+        assert_eq!(cpu.rpl[..4], [1, 2, 3, 0]);
+    }
+
+    /* ----------  XO-CHIP  ---------- */
+
+    #[test]
+    fn long_i() {
+        let mut cpu = xo_chip(&[0xF000, 0xBEEF, 0x6001]);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.i, cpu.pc), (0xBEEF, 0x204));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.v[0], 1);
+    }
+
+    #[test]
+    fn skip_over_long_i() {
+        // V0 is 0, so each skip is taken: once over all 4 bytes of F000,
+        // and once over a regular instruction
+        let mut cpu = xo_chip(&[0x3000, 0xF000, 0x1234, 0x3000, 0x6105,
+                                0x6206]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.i, 0);
+        assert_eq!((cpu.v[1], cpu.v[2]), (0, 6));
+        assert_eq!(cpu.pc, 0x20C);
+    }
+
+    #[test]
+    fn pc_wraps_around() {
+        let mut cpu = xo_chip(&[]);
+        cpu.ram.store_u16(0xFFFC, 0x3001).unwrap();
+        cpu.ram.store_u16(0xFFFE, 0x6001).unwrap();
+
+        cpu.pc = 0xFFFE;
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x0000);
+
+        // skipping past the end too
+        cpu.pc = 0xFFFC;
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x0000);
+    }
+
+    #[test]
+    fn save_load_register_range() {
+        let mut cpu = xo_chip(&[0xA300, 0x5132, 0x5312, 0x6100, 0x5133]);
+        cpu.v[..4].copy_from_slice(&[0, 1, 2, 3]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.ram.load_u8(0x300).unwrap(), 1);
+        assert_eq!(cpu.ram.load_u8(0x302).unwrap(), 3);
+        assert_eq!(cpu.i, 0x300);
+
+        // in reverse, when x > y
+        run(&mut cpu, 1);
+        assert_eq!(cpu.ram.load_u8(0x300).unwrap(), 3);
+        assert_eq!(cpu.ram.load_u8(0x302).unwrap(), 1);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.v[..4], [0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn planes() {
+        // draw a 1 pixel sprite on plane 2, then on both planes
+        let mut cpu = xo_chip(&[0xF201, 0xA300, 0xD001, 0xF301, 0x6101,
+                                0xD011]);
+        cpu.ram.store_u8(0x300, 0x80).unwrap();
+        cpu.ram.store_u8(0x301, 0x80).unwrap();
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.screen().planes(), 2);
+        assert_eq!(cpu.screen().pixel(0, 0), 2);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.screen().pixel(0, 1), 3);
+    }
+
+    #[test]
+    fn audio() {
+        let mut cpu = xo_chip(&[0xA300, 0xF002, 0x6070, 0xF03A]);
+        for offset in 0..16 {
+            cpu.ram.store_u8(0x300 + offset, offset as u8).unwrap();
+        }
+        run(&mut cpu, 4);
+
+        let expected = (0..16).collect::<Vec<u8>>();
+        assert_eq!(cpu.audio.pattern[..], expected[..]);
+        assert_eq!(cpu.audio.pitch, 0x70);
+    }
+
+    /* ----------  QUIRKS  ---------- */
+
+    #[test]
+    fn shift_vy() {
+        // VF is the bit shifted out of whichever register was shifted
+        for &(quirks, expected) in &[(Quirks::default(), [0, 0, 0x10]),
+                                     (Quirks::cosmac_vip(), [1, 1, 6])] {
+            let mut cpu = load(quirks, ram::RAM::new(),
+                               &[0x6103, 0x6208, 0x8016, 0x821E]);
+            run(&mut cpu, 3);
+            assert_eq!([cpu.v[0], cpu.v[0xF]], expected[..2]);
+            run(&mut cpu, 1);
+            assert_eq!(cpu.v[2], expected[2]);
+        }
+    }
+
+    #[test]
+    fn load_store() {
+        for &(quirks, expected) in &[(Quirks::cosmac_vip(), 0x303),
+                                     (Quirks::chip48(), 0x302),
+                                     (Quirks::schip11(), 0x300)] {
+            let mut cpu = load(quirks, ram::RAM::new(),
+                               &[0xA300, 0xF255, 0xA300, 0xF265]);
+            run(&mut cpu, 2);
+            assert_eq!(cpu.i, expected);
+            run(&mut cpu, 2);
+            assert_eq!(cpu.i, expected);
+        }
+    }
+
+    #[test]
+    fn jump_vx() {
+        for &(quirks, expected) in &[(Quirks::default(), 0x224),
+                                     (Quirks::chip48(), 0x228)] {
+            let mut cpu = load(quirks, ram::RAM::new(),
+                               &[0x6004, 0x6208, 0xB220]);
+            run(&mut cpu, 3);
+            assert_eq!(cpu.pc, expected);
+        }
+    }
+
+    #[test]
+    fn vf_reset() {
+        for &(quirks, expected) in &[(Quirks::default(), 5),
+                                     (Quirks::cosmac_vip(), 0)] {
+            let mut cpu = load(quirks, ram::RAM::new(),
+                               &[0x6001, 0x6102, 0x6F05, 0x8011]);
+            run(&mut cpu, 4);
+            assert_eq!((cpu.v[0], cpu.v[0xF]), (3, expected));
+        }
+    }
+
+    #[test]
+    fn clip_sprites() {
+        let wrapped = vec![(0, 0), (1, 0), (2, 0), (3, 0),
+                           (60, 0), (61, 0), (62, 0), (63, 0)];
+        for &(quirks, ref expected) in &[(Quirks::default(), wrapped),
+                                         (Quirks::cosmac_vip(),
+                                          vec![(60, 0), (61, 0), (62, 0),
+                                               (63, 0)])] {
+            let mut cpu = load(quirks, ram::RAM::new(),
+                               &[0x603C, 0xA300, 0xD011]);
+            cpu.ram.store_u8(0x300, 0xFF).unwrap();
+            run(&mut cpu, 3);
+            assert_eq!(&lit(&cpu), expected);
+        }
+    }
+
+    #[test]
+    fn i_overflow_vf() {
+        for &(quirks, expected) in &[(Quirks::default(), 0),
+                                     (Quirks::amiga(), 1)] {
+            let mut cpu = load(quirks, ram::RAM::new(),
+                               &[0xAFFF, 0x6001, 0xF01E]);
+            run(&mut cpu, 3);
+            assert_eq!((cpu.i, cpu.v[0xF]), (0x1000, expected));
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+
+use cpu::CPU;
+use error::EmuError;
+use screenshot;
+use types::Chip8Utils;
+
+// Crash reports.
+//
+// When the CPU halts with an error, everything needed to figure out what went
+// wrong is gathered up into a plain text report: the error itself, the
+// registers, the last few instructions that ran, the memory around PC and I,
+// and the final screen.
+
+// How many of the most recently executed instructions are kept
+const TRACE_LEN: usize = 32;
+
+// Bytes of memory shown before / after PC and I
+const DUMP_BEFORE: usize = 16;
+const DUMP_AFTER: usize = 32;
+
+// Ring buffer of the last instructions executed, as (address, opcode)
+pub struct Trace {
+    entries: VecDeque<(u16, u16)>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace { entries: VecDeque::with_capacity(TRACE_LEN) }
+    }
+
+    // Records the instruction the CPU is about to execute
+    pub fn record(&mut self, cpu: &CPU) {
+        let instr = cpu.ram().load_u16(cpu.pc()).unwrap_or(0);
+        if self.entries.len() == TRACE_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((cpu.pc(), instr));
+    }
+
+    // Forgets everything, for when the CPU jumps to a restored state (and
+    // the instructions so far no longer led up to it)
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+pub fn report(cpu: &CPU, trace: &Trace, err: &EmuError) -> String {
+    let mut out = String::new();
+
+    out += "=== AC8E crash report ===\n";
+    out += &format!("{}\n", err);
+
+    // the CPU always says where it crashed, but just in case
+    let pc = err.pc().unwrap_or_else(|| cpu.pc());
+    out += "\n--- Registers\n";
+    out += &format_regs(cpu, pc);
+
+    out += &format!("\n--- Last {} instructions\n", trace.entries.len());
+    // the last one is the instruction that crashed
+    let last = trace.entries.len().saturating_sub(1);
+    for (i, &(at, instr)) in trace.entries.iter().enumerate() {
+        out += &format!("{} {:04x}: {:04x}  {}\n",
+                        if i == last { "->" } else { "  " },
+                        at,
+                        instr,
+                        instr.disasm());
+    }
+
+    out += &format!("\n--- Memory around PC (0x{:03x})\n", pc);
+    out += &hex_dump(cpu, pc);
+    out += &format!("\n--- Memory around I (0x{:03x})\n", cpu.i());
+    out += &hex_dump(cpu, cpu.i());
+    if let Some(addr) = err.addr().filter(|addr| *addr != cpu.i()) {
+        out += &format!("\n--- Memory around 0x{:03x}\n", addr);
+        out += &hex_dump(cpu, addr);
+    }
+
+    out += "\n--- Screen\n";
+    out += &screenshot::text(cpu.screen());
+
+    out
+}
+
+// V0-VF, PC, I, the timers and the stack, one line per group (PC is passed
+// in, since a crashed CPU may not be pointing at the faulting instruction)
+pub fn format_regs(cpu: &CPU, pc: u16) -> String {
+    let mut out = String::new();
+
+    let v = cpu.v();
+    for row in 0..2 {
+        let regs = (0..8)
+            .map(|i| row * 8 + i)
+            .map(|i| format!("V{:X}={:02x}", i, v[i]))
+            .collect::<Vec<String>>();
+        out += &format!("{}\n", regs.join(" "));
+    }
+
+    let (dt, st) = cpu.timers();
+    out += &format!("PC={:04x} I={:04x} DT={:02x} ST={:02x} cycle={}\n",
+                    pc,
+                    cpu.i(),
+                    dt,
+                    st,
+                    cpu.cycles());
+
+    let stack = cpu.stack()
+        .iter()
+        .map(|addr| format!("{:04x}", addr))
+        .collect::<Vec<String>>();
+    out += &format!("stack: [{}]\n", stack.join(", "));
+
+    out
+}
+
+// Rows of 16 bytes around addr, with addr's byte marked
+fn hex_dump(cpu: &CPU, addr: u16) -> String {
+    let ram = cpu.ram();
+    let start = (addr as usize).saturating_sub(DUMP_BEFORE) & !0xF;
+    let end = (addr as usize + DUMP_AFTER).min(ram.size());
+
+    let mut out = String::new();
+    for row in (start..end).step_by(16) {
+        out += &format!("{:04x}:", row);
+        for at in row..(row + 16).min(ram.size()) {
+            let byte = ram.load_u8(at as u16).unwrap_or(0);
+            let mark = if at == addr as usize { '>' } else { ' ' };
+            out += &format!("{}{:02x}", mark, byte);
+        }
+        out.push('\n');
+    }
+    if out.is_empty() {
+        out += "(out of bounds)\n";
+    }
+    out
+}
+
+pub fn write(report: &str, path: &str) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut f| f.write_all(report.as_bytes()))
+        .map_err(|e| format!("[Crash] Couldn't write '{}': {}", path, e))
+}
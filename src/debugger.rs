@@ -0,0 +1,253 @@
+use std::io;
+use std::io::Write;
+
+use cpu::CPU;
+use crash;
+use crash::Trace;
+use rewind::History;
+use types::Chip8Utils;
+
+// A simple gdb-style debugger.
+//
+// The main loop asks the debugger whether it should stop before every
+// instruction. When it does, the frontend is suspended, and the user gets a
+// command prompt on stdin / stdout until they continue (or quit).
+
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+pub struct Debugger {
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<u16>,
+
+    // stop after this many more instructions
+    steps: Option<u32>,
+    // don't immediately re-trigger the breakpoint we just resumed from
+    resume_pc: Option<u16>,
+    // why we stopped, shown when the prompt comes up
+    reason: Option<String>,
+
+    last_cmd: String,
+}
+
+const HELP: &str = "\
+Commands:
+    s, step [n]         execute n instructions (default: 1)
+    c, continue         resume execution
+    b, break [addr]     toggle a breakpoint at addr, or list breakpoints
+    w, watch [addr]     toggle a memory-write watchpoint at addr, or list
+                        watchpoints
+    r, regs             show registers, stack and timers
+    x <addr> [len]      hex dump len bytes of memory (default: 16)
+    l, list [addr]      disassemble around addr (default: PC)
+    back [n]            rewind n frames (default: 1)
+    q, quit             exit the emulator
+    h, help             print this message
+An empty line repeats the last command.";
+
+// Parses an address, with or without a `0x` prefix (always hex)
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim_start_matches("0x");
+    u16::from_str_radix(s, 16).ok()
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: vec![],
+            watchpoints: vec![],
+
+            steps: None,
+            resume_pc: None,
+            reason: None,
+
+            last_cmd: String::new(),
+        }
+    }
+
+    // Stop before the next instruction
+    pub fn interrupt(&mut self) {
+        self.steps = Some(0);
+    }
+
+    // Called before every instruction. Returns true if execution should stop
+    pub fn should_break(&mut self, cpu: &mut CPU) -> bool {
+        let pc = cpu.pc();
+
+        if let Some(addr) = cpu.ram_mut().take_watch_hit() {
+            self.reason = Some(format!("watchpoint: write to 0x{:03x}",
+                                       addr));
+            return true;
+        }
+
+        match self.steps {
+            Some(0) => return true,
+            Some(n) => self.steps = Some(n - 1),
+            None => (),
+        }
+
+        if self.resume_pc.take() != Some(pc) &&
+           self.breakpoints.contains(&pc) {
+            self.reason = Some(format!("breakpoint at 0x{:03x}", pc));
+            return true;
+        }
+
+        false
+    }
+
+    // Interactive prompt. Returns once the user continues, steps, or quits.
+    pub fn repl(&mut self,
+                cpu: &mut CPU,
+                history: &mut History,
+                trace: &mut Trace)
+                -> Action {
+        if let Some(reason) = self.reason.take() {
+            println!("\n*** {}", reason);
+        }
+        self.print_regs(cpu);
+        self.print_disasm(cpu, cpu.pc());
+
+        loop {
+            print!("(ac8e) ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => return Action::Quit, // EOF
+                Ok(_) => (),
+            }
+
+            // repeat the last command on an empty line
+            let line = line.trim().to_string();
+            let line = if line.is_empty() {
+                self.last_cmd.clone()
+            } else {
+                line
+            };
+            self.last_cmd = line.clone();
+
+            let args = line.split_whitespace().collect::<Vec<&str>>();
+            let arg = |i: usize| args.get(i).cloned();
+
+            match arg(0) {
+                Some("s") | Some("step") => {
+                    let n = arg(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+                    self.steps = Some(n);
+                    self.resume_pc = Some(cpu.pc());
+                    return Action::Continue;
+                }
+                Some("c") | Some("continue") => {
+                    self.steps = None;
+                    self.resume_pc = Some(cpu.pc());
+                    return Action::Continue;
+                }
+                Some("b") | Some("break") => match arg(1).map(parse_addr) {
+                    Some(Some(addr)) => {
+                        toggle(&mut self.breakpoints, addr, "breakpoint")
+                    }
+                    Some(None) => println!("invalid address"),
+                    None => list("breakpoints", &self.breakpoints),
+                },
+                Some("w") | Some("watch") => {
+                    match arg(1).map(parse_addr) {
+                        Some(Some(addr)) => {
+                            toggle(&mut self.watchpoints, addr, "watchpoint");
+                            cpu.ram_mut().set_watchpoints(&self.watchpoints);
+                        }
+                        Some(None) => println!("invalid address"),
+                        None => list("watchpoints", &self.watchpoints),
+                    }
+                }
+                Some("r") | Some("regs") => self.print_regs(cpu),
+                Some("x") => match arg(1).and_then(parse_addr) {
+                    Some(addr) => {
+                        let len = arg(2).and_then(|n| n.parse().ok());
+                        self.print_mem(cpu, addr, len.unwrap_or(16));
+                    }
+                    None => println!("usage: x <addr> [len]"),
+                },
+                Some("l") | Some("list") => {
+                    let addr = arg(1).and_then(parse_addr);
+                    self.print_disasm(cpu, addr.unwrap_or_else(|| cpu.pc()));
+                }
+                Some("back") => {
+                    let n = arg(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+                    let rewound = history.rewind(cpu, n);
+                    if rewound.is_ok() {
+                        trace.clear();
+                    }
+                    match rewound {
+                        Ok(0) => println!("no more history"),
+                        Ok(n) => {
+                            println!("rewound {} frame(s)", n);
+                            self.print_regs(cpu);
+                            self.print_disasm(cpu, cpu.pc());
+                        }
+                        Err(why) => println!("{}", why),
+                    }
+                }
+                Some("q") | Some("quit") => return Action::Quit,
+                Some("h") | Some("help") => println!("{}", HELP),
+                Some(cmd) => println!("unknown command '{}' (try 'help')", cmd),
+                None => (),
+            }
+        }
+    }
+
+    fn print_regs(&self, cpu: &CPU) {
+        print!("{}", crash::format_regs(cpu, cpu.pc()));
+    }
+
+    fn print_mem(&self, cpu: &CPU, addr: u16, len: usize) {
+        for row in (0..len).step_by(16) {
+            let start = addr as usize + row;
+            let bytes = (start..(start + 16).min(addr as usize + len))
+                .filter_map(|a| cpu.ram().load_u8(a as u16).ok())
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<String>>();
+            if bytes.is_empty() {
+                break;
+            }
+            println!("{:04x}: {}", start, bytes.join(" "));
+        }
+    }
+
+    // A window of instructions around addr
+    fn print_disasm(&self, cpu: &CPU, addr: u16) {
+        let start = addr.saturating_sub(8);
+        for at in (start..addr.saturating_add(10)).step_by(2) {
+            let instr = match cpu.ram().load_u16(at) {
+                Ok(instr) => instr,
+                Err(_) => break,
+            };
+
+            println!("{}{} {:04x}: {:04x}  {}",
+                     if at == cpu.pc() { "->" } else { "  " },
+                     if self.breakpoints.contains(&at) { "*" } else { " " },
+                     at,
+                     instr,
+                     instr.disasm());
+        }
+    }
+}
+
+fn toggle(addrs: &mut Vec<u16>, addr: u16, what: &str) {
+    if let Some(i) = addrs.iter().position(|a| *a == addr) {
+        addrs.remove(i);
+        println!("removed {} at 0x{:03x}", what, addr);
+    } else {
+        addrs.push(addr);
+        println!("added {} at 0x{:03x}", what, addr);
+    }
+}
+
+fn list(what: &str, addrs: &[u16]) {
+    if addrs.is_empty() {
+        println!("no {}", what);
+    }
+    for addr in addrs {
+        println!("{} at 0x{:03x}", what, addr);
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use types::*;
 
 pub fn disasm(word: u16) -> String {
@@ -9,14 +11,27 @@ pub fn disasm(word: u16) -> String {
     let kk = word & 0x00FF;
 
     match word.nibble_at(0) {
-        0x0 if nnn == 0x0E0 => format!("CLS"),
-        0x0 if nnn == 0x0EE => format!("RET"),
+        0x0 if nnn & 0xFF0 == 0x0C0 => format!("SCD     {}", word.nibble_at(3)),
+        0x0 if nnn & 0xFF0 == 0x0D0 => format!("SCU     {}", word.nibble_at(3)),
+        0x0 if nnn == 0x0E0 => "CLS".to_string(),
+        0x0 if nnn == 0x0EE => "RET".to_string(),
+        0x0 if nnn == 0x230 => "CLS     ; HIRES CHIP-8".to_string(),
+        0x0 if nnn == 0x0FB => "SCR".to_string(),
+        0x0 if nnn == 0x0FC => "SCL".to_string(),
+        0x0 if nnn == 0x0FD => "EXIT".to_string(),
+        0x0 if nnn == 0x0FE => "LOW".to_string(),
+        0x0 if nnn == 0x0FF => "HIGH".to_string(),
         0x0 => format!(".word   0x{:04x}", word),
         0x1 => format!("JP      0x{:03x}", nnn),
         0x2 => format!("CALL    0x{:03x}", nnn),
         0x3 => format!("SE      V{:x}, {}", x, kk),
         0x4 => format!("SNE     V{:x}, {}", x, kk),
-        0x5 => format!("SE      V{:x}, V{:x}", x, y),
+        0x5 => match word.nibble_at(3) {
+            0x0 => format!("SE      V{:x}, V{:x}", x, y),
+            0x2 => format!("LD      [I], V{:x}-V{:x}", x, y),
+            0x3 => format!("LD      V{:x}-V{:x}, [I]", x, y),
+            _ => format!(".word   0x{:04x}", word),
+        },
         0x6 => format!("LD      V{:x}, {}", x, kk),
         0x7 => format!("ADD     V{:x}, {}", x, kk),
         0x8 => match word.nibble_at(3) {
@@ -39,6 +54,9 @@ pub fn disasm(word: u16) -> String {
         0xE if kk == 0x9E => format!("SKP     V{:x}", x),
         0xE if kk == 0xA1 => format!("SKNP    V{:x}", x),
         0xE => format!(".word   0x{:04x}", word),
+        0xF if nnn == 0x000 => "LD      I, long".to_string(),
+        0xF if kk == 0x01 => format!("PLANE   {}", x),
+        0xF if nnn == 0x002 => "AUDIO".to_string(),
         0xF => match kk {
             0x07 => format!("LD      V{:x}, DT", x),
             0x0A => format!("LD      V{:x}, K", x),
@@ -46,11 +64,15 @@ pub fn disasm(word: u16) -> String {
             0x18 => format!("LD      ST, V{:x}", x),
             0x1E => format!("ADD     I, V{:x}", x),
             0x29 => format!("LD      F, V{:x}", x),
+            0x30 => format!("LD      HF, V{:x}", x),
+            0x3A => format!("PITCH   V{:x}", x),
             0x33 => format!("LD      B, V{:x}", x),
             0x55 => format!("LD      [I], V{:x}", x),
             0x65 => format!("LD      V{:x}, [I]", x),
+            0x75 => format!("LD      R, V{:x}", x),
+            0x85 => format!("LD      V{:x}, R", x),
             _ => format!(".word   0x{:04x}", word),
         },
-        _ => format!(""),
+        _ => String::new(),
     }
 }
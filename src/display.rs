@@ -1,9 +1,18 @@
 #![allow(dead_code)]
 
+#[cfg(feature = "ncurses")]
+extern crate libc;
+#[cfg(feature = "ncurses")]
 extern crate ncurses;
 
+use std::cell::Cell;
+use std::cell::Ref;
 use std::cell::RefCell;
 
+use graphics;
+use savestate;
+use tty;
+
 /*======================================
 =            Display Traits            =
 ======================================*/
@@ -13,14 +22,40 @@ pub trait Update {
     // clear screen
     fn clear(&self);
     // Draw to screen RAM according to chip8 spec
-    fn draw(&self, x: u8, y: u8, ram: &[u8]) -> bool;
+    // If `clip` is set, sprites are cut off at the edges of the screen
+    // instead of wrapping around.
+    fn draw(&self, x: u8, y: u8, ram: &[u8], clip: bool) -> bool;
+    // Draw a 16x16 SCHIP sprite (2 bytes per row)
+    fn draw_wide(&self, x: u8, y: u8, ram: &[u8], clip: bool) -> bool;
+
+    // SCHIP scrolling (XO-CHIP adds scroll_up)
+    fn scroll_down(&self, n: u8);
+    fn scroll_up(&self, n: u8);
+    fn scroll_left(&self);
+    fn scroll_right(&self);
+
+    // XO-CHIP bitplane selection (bitmask, 0-3)
+    fn set_planes(&self, planes: u8);
+    fn planes(&self) -> u8;
+
+    // Switch the active screen resolution (clears the screen)
+    fn set_resolution(&self, res: Resolution);
+    fn resolution(&self) -> Resolution;
 }
 
 // Trait exposed to main loop to actually render the screen
 pub trait Render {
     fn init(&self); // run once at start
     fn uninit(&self); // run once at end
-    fn render(&self, beep: bool); // run every frame
+    fn render(&self, screen: &ScreenRAM); // run every frame
+
+    // temporarily hand the terminal back (eg: to the debugger)
+    fn suspend(&self) {
+        self.uninit()
+    }
+    fn resume(&self) {
+        self.init()
+    }
 }
 
 /*==================================
@@ -29,109 +64,298 @@ pub trait Render {
 // This struct implements the underlying DRAW instruction logic, and holds the
 // bit-arrays that represent the screen.
 
-struct ScreenRAM {
-    pixels: RefCell<[[bool; 64]; 32]>,
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resolution {
+    Low,  // 64x32, regular CHIP-8
+    Tall, // 64x64, HIRES CHIP-8
+    High, // 128x64, SCHIP extended mode
+}
+
+impl Resolution {
+    pub fn width(&self) -> usize {
+        match *self {
+            Resolution::Low | Resolution::Tall => 64,
+            Resolution::High => 128,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match *self {
+            Resolution::Low => 32,
+            Resolution::Tall | Resolution::High => 64,
+        }
+    }
+}
+
+// Pixels are stored row-major in a single buffer, which is resized whenever
+// the resolution changes.
+// Each pixel holds one bit per XO-CHIP bitplane, so the value of a pixel is a
+// color in the range 0-3. Plain (S)CHIP-8 programs only ever touch plane 1.
+
+pub const PLANES: u8 = 2;
+
+pub struct ScreenRAM {
+    pixels: RefCell<Vec<u8>>,
+    res: Cell<Resolution>,
+    planes: Cell<u8>,
 }
 
 impl ScreenRAM {
     pub fn new() -> ScreenRAM {
-        ScreenRAM { pixels: RefCell::new([[false; 64]; 32]) }
+        let res = Resolution::Low;
+        ScreenRAM {
+            pixels: RefCell::new(vec![0; res.width() * res.height()]),
+            res: Cell::new(res),
+            planes: Cell::new(1),
+        }
     }
-}
 
-impl Update for ScreenRAM {
-    fn clear(&self) {
-        *self.pixels.borrow_mut() = [[false; 64]; 32];
+    pub fn width(&self) -> usize {
+        self.res.get().width()
     }
-    fn draw(&self, x: u8, y: u8, ram: &[u8]) -> bool {
+
+    pub fn height(&self) -> usize {
+        self.res.get().height()
+    }
+
+    // Get the color (0-3) of a pixel
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels.borrow()[y * self.width() + x]
+    }
+
+    // All the pixels, row by row
+    pub fn pixels(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.pixels.borrow(), |p| p.as_slice())
+    }
+
+    // Set the color (0-3) of a pixel directly, bypassing the bitplanes
+    pub fn set_pixel(&self, x: usize, y: usize, color: u8) {
+        let width = self.width();
+        self.pixels.borrow_mut()[y * width + x] = color;
+    }
+
+    pub fn save_state(&self, w: &mut savestate::Writer) {
+        w.u8(match self.res.get() {
+            Resolution::Low => 0,
+            Resolution::Tall => 1,
+            Resolution::High => 2,
+        });
+        w.u8(self.planes.get());
+        w.blob(&self.pixels.borrow());
+    }
+
+    pub fn load_state(r: &mut savestate::Reader) -> Result<ScreenRAM, String> {
+        let res = match r.u8()? {
+            0 => Resolution::Low,
+            1 => Resolution::Tall,
+            2 => Resolution::High,
+            n => return Err(format!("[State] Invalid resolution {}", n)),
+        };
+        let planes = r.u8()?;
+        let pixels = r.blob()?;
+
+        if pixels.len() != res.width() * res.height() {
+            return Err("[State] Screen size doesn't match resolution"
+                .to_string());
+        }
+
+        Ok(ScreenRAM {
+            pixels: RefCell::new(pixels.to_vec()),
+            res: Cell::new(res),
+            planes: Cell::new(planes),
+        })
+    }
+
+    // Iterate over the selected bitplanes (as bitmasks)
+    fn selected_planes(&self) -> Vec<u8> {
+        (0..PLANES)
+            .map(|plane| 1 << plane)
+            .filter(|mask| self.planes.get() & mask != 0)
+            .collect()
+    }
+
+    // XOR a row of `width` bits (MSB first) onto a plane, starting at
+    // (x, y). Pixels that fall off the edges either wrap around, or are
+    // clipped. Returns true if any pixels were erased.
+    fn draw_row(&self,
+                (x, y): (usize, usize),
+                bits: u16,
+                width: usize,
+                plane: u8,
+                clip: bool)
+                -> bool {
         let mut collision = false;
+        let mut p = self.pixels.borrow_mut();
+
+        let (w, h) = (self.width(), self.height());
+        if clip && y >= h {
+            return false;
+        }
 
-        for (row, byte) in ram.iter().enumerate() {
-            // Transform byte into bitvector
-            let bits = (0..8)
-                .rev()
-                .map(|x| ((*byte >> x) % 2) == 1)
-                .collect::<Vec<bool>>();
+        let row = (y % h) * w;
+        for bit in 0..width {
+            if clip && x + bit >= w {
+                break;
+            }
 
-            for bit in 0..8usize {
-                let y = (y as usize + row) % 32;
-                let x = (x as usize + bit) % 64;
+            let i = row + (x + bit) % w;
+            let on = (bits >> (width - 1 - bit)) & 1 == 1;
 
-                let mut p = self.pixels.borrow_mut();
+            // check collision
+            if p[i] & plane != 0 && on {
+                collision = true;
+            }
 
-                // check collision
-                if p[y][x] == true && bits[bit] == true {
-                    collision = true;
-                }
+            // do the xor
+            if on {
+                p[i] ^= plane;
+            }
+        }
 
-                // do the xor
-                p[y][x] ^= bits[bit];
+        collision
+    }
+
+    // Move the contents of the selected planes by (dx, dy), filling the
+    // uncovered area with blank pixels.
+    fn scroll(&self, dx: isize, dy: isize) {
+        let mask = self.planes.get();
+        let (w, h) = (self.width() as isize, self.height() as isize);
+        let old = self.pixels.borrow().clone();
+        let mut p = self.pixels.borrow_mut();
+
+        for y in 0..h {
+            for x in 0..w {
+                let (sx, sy) = (x - dx, y - dy);
+                let src = if sx >= 0 && sx < w && sy >= 0 && sy < h {
+                    old[(sy * w + sx) as usize]
+                } else {
+                    0
+                };
+
+                let i = (y * w + x) as usize;
+                p[i] = (p[i] & !mask) | (src & mask);
+            }
+        }
+    }
+}
+
+impl Update for ScreenRAM {
+    fn clear(&self) {
+        let mask = self.planes.get();
+        for p in self.pixels.borrow_mut().iter_mut() {
+            *p &= !mask;
+        }
+    }
+    // With multiple planes selected, the sprite data for each plane is laid
+    // out one after the other.
+    // The starting coordinates always wrap around, even when clipping.
+    fn draw(&self, x: u8, y: u8, ram: &[u8], clip: bool) -> bool {
+        let planes = self.selected_planes();
+        if planes.is_empty() {
+            return false;
+        }
+
+        let (x, y) = (x as usize % self.width(), y as usize % self.height());
+        let rows = ram.len() / planes.len();
+        let mut collision = false;
+        for (plane, data) in planes.iter().zip(ram.chunks(rows.max(1))) {
+            for (row, byte) in data.iter().enumerate() {
+                collision |=
+                    self.draw_row((x, y + row), *byte as u16, 8, *plane, clip);
             }
         }
 
         collision
     }
+    fn draw_wide(&self, x: u8, y: u8, ram: &[u8], clip: bool) -> bool {
+        let planes = self.selected_planes();
+        let (x, y) = (x as usize % self.width(), y as usize % self.height());
+        let mut collision = false;
+        for (plane, data) in planes.iter().zip(ram.chunks(32)) {
+            for (row, word) in data.chunks(2).enumerate() {
+                let bits = (word[0] as u16) << 8 | *word.get(1).unwrap_or(&0)
+                    as u16;
+                collision |=
+                    self.draw_row((x, y + row), bits, 16, *plane, clip);
+            }
+        }
+
+        collision
+    }
+
+    fn scroll_down(&self, n: u8) {
+        self.scroll(0, n as isize)
+    }
+    fn scroll_up(&self, n: u8) {
+        self.scroll(0, -(n as isize))
+    }
+    fn scroll_left(&self) {
+        self.scroll(-4, 0)
+    }
+    fn scroll_right(&self) {
+        self.scroll(4, 0)
+    }
+
+    fn set_planes(&self, planes: u8) {
+        self.planes.set(planes & 0b11);
+    }
+    fn planes(&self) -> u8 {
+        self.planes.get()
+    }
+
+    fn set_resolution(&self, res: Resolution) {
+        self.res.set(res);
+        *self.pixels.borrow_mut() = vec![0; res.width() * res.height()];
+    }
+    fn resolution(&self) -> Resolution {
+        self.res.get()
+    }
 }
 
 /*=================================
 =            Renderers            =
 =================================*/
 
-// Renderer structs are just simple wrappers around ScreenRAM.
-// The `Update` trait simply calls the equivalent Screen RAM's functions, and
-// the `Render` trait is used to implement different rendering modes
+// Characters used to draw each pixel color in text-based renderers
+pub const GLYPHS: [char; 4] = [' ', 'X', 'O', '#'];
+
+// RGB colors for each pixel value (both planes off, plane 1, plane 2, both),
+// in renderers / screenshots that do color
+pub type Palette = [[u8; 3]; 4];
+
+pub const PALETTE: Palette = [[0x00, 0x00, 0x00],
+                              [0xFF, 0xFF, 0xFF],
+                              [0xAA, 0xAA, 0xAA],
+                              [0x55, 0x55, 0x55]];
+
+// Renderers don't own any screen memory. Every frame, they are handed the
+// ScreenRAM that the CPU draws into, and present it however they see fit.
 
 /* ----------  Null Renderer  ---------- */
 
-pub struct NullDisplay {
-    screen: ScreenRAM,
-}
+pub struct NullDisplay {}
 
 impl NullDisplay {
     pub fn new() -> NullDisplay {
-        NullDisplay { screen: ScreenRAM::new() }
-    }
-}
-
-// simply call the underlying screenRAM methods
-impl Update for NullDisplay {
-    fn clear(&self) {
-        self.screen.clear()
-    }
-    fn draw(&self, x: u8, y: u8, ram: &[u8]) -> bool {
-        self.screen.draw(x, y, ram)
+        NullDisplay {}
     }
 }
 
 impl Render for NullDisplay {
     fn init(&self) {}
     fn uninit(&self) {}
-    fn render(&self, beep: bool) {
-        drop(beep)
-    }
+    fn render(&self, _screen: &ScreenRAM) {}
 }
 
 /* ----------  Terminal Renderer  ---------- */
 // Basic renderer to output to terminal.
 // ** Cannot be extended with associated realtime input!
 
-pub struct TermDisplay {
-    screen: ScreenRAM,
-}
+pub struct TermDisplay {}
 
 impl TermDisplay {
     pub fn new() -> TermDisplay {
-        TermDisplay { screen: ScreenRAM::new() }
-    }
-}
-
-// simply call the underlying screenRAM methods
-impl Update for TermDisplay {
-    fn clear(&self) {
-        self.screen.clear()
-    }
-    fn draw(&self, x: u8, y: u8, ram: &[u8]) -> bool {
-        self.screen.draw(x, y, ram)
+        TermDisplay {}
     }
 }
 
@@ -139,22 +363,15 @@ impl Render for TermDisplay {
     fn init(&self) {}
     fn uninit(&self) {}
 
-    fn render(&self, beep: bool) {
+    fn render(&self, screen: &ScreenRAM) {
         print!("\x1b[2J\x1b[1;1H"); // magic chars to clear the term screen
 
-        for y in 0..32 {
-            for x in 0..64 {
-                print!("{}",
-                       format!("{}", self.screen.pixels.borrow()[y][x] as u8)
-                           .replace("0", " ")
-                           .replace("1", "X"));
+        for y in 0..screen.height() {
+            for x in 0..screen.width() {
+                print!("{}", GLYPHS[screen.pixel(x, y) as usize]);
             }
             println!();
         }
-
-        if beep {
-            println!("\u{0007}");
-        }
     }
 }
 
@@ -162,32 +379,125 @@ impl Render for TermDisplay {
 /* ----------  Ncurses Renderer  ---------- */
 // ncurses based terminal renderer
 // faster, and supports realtime input
+//
+// Pixels are either drawn one per character cell, or packed several to a
+// cell using Unicode half blocks / Braille patterns, which squashes the
+// picture less, and fits hires SCHIP games in a regular 80x24 terminal.
+
+#[cfg(feature = "ncurses")]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Cells {
+    Ascii,     // 1x1 pixels per cell, drawn with GLYPHS
+    HalfBlock, // 1x2 pixels per cell
+    Braille,   // 2x4 pixels per cell
+}
+
+// Braille dot positions, in the order of their bits in U+2800 - U+28FF
+#[cfg(feature = "ncurses")]
+const BRAILLE_DOTS: [(usize, usize); 8] =
+    [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+
+#[cfg(feature = "ncurses")]
+impl Cells {
+    // Pixels per cell, as (width, height)
+    fn size(self) -> (usize, usize) {
+        match self {
+            Cells::Ascii => (1, 1),
+            Cells::HalfBlock => (1, 2),
+            Cells::Braille => (2, 4),
+        }
+    }
 
+    // The character for the cell at (x, y) (in cells), and the color to draw
+    // it in. A cell can only have one color, so the highest one wins.
+    fn glyph(self, screen: &ScreenRAM, pixels: &[u8], x: usize, y: usize)
+             -> (char, u8) {
+        let (w, h) = self.size();
+        let at = |dx: usize, dy: usize| {
+            pixels[(y * h + dy) * screen.width() + x * w + dx]
+        };
+
+        match self {
+            Cells::Ascii => (GLYPHS[at(0, 0) as usize], at(0, 0)),
+            Cells::HalfBlock => {
+                let (top, bottom) = (at(0, 0), at(0, 1));
+                let glyph = match (top != 0, bottom != 0) {
+                    (false, false) => ' ',
+                    (true, false) => '\u{2580}',
+                    (false, true) => '\u{2584}',
+                    (true, true) => '\u{2588}',
+                };
+                (glyph, top.max(bottom))
+            }
+            Cells::Braille => {
+                let mut dots = 0;
+                let mut color = 0;
+                for (bit, &(dx, dy)) in BRAILLE_DOTS.iter().enumerate() {
+                    if at(dx, dy) != 0 {
+                        dots |= 1 << bit;
+                        color = color.max(at(dx, dy));
+                    }
+                }
+                (char::from_u32(0x2800 + dots).unwrap(), color)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ncurses")]
 pub struct NcursesDisplay {
-    screen: ScreenRAM,
+    cells: Cells,
+
+    // the cells presented last frame, so only the ones that changed since
+    // need to be redrawn (empty if the whole screen needs redrawing)
+    last: RefCell<Vec<(char, u8)>>,
+    last_res: Cell<Resolution>,
+    // reused to draw runs of changed cells
+    run: RefCell<String>,
 }
 
+#[cfg(feature = "ncurses")]
 impl NcursesDisplay {
     pub fn new() -> NcursesDisplay {
-        NcursesDisplay { screen: ScreenRAM::new() }
+        NcursesDisplay::with_cells(Cells::Ascii)
     }
-}
 
-// simply call the underlying screenRAM methods
-impl Update for NcursesDisplay {
-    fn clear(&self) {
-        self.screen.clear()
+    pub fn with_cells(cells: Cells) -> NcursesDisplay {
+        NcursesDisplay {
+            cells,
+
+            last: RefCell::new(vec![]),
+            last_res: Cell::new(Resolution::Low),
+            run: RefCell::new(String::new()),
+        }
     }
-    fn draw(&self, x: u8, y: u8, ram: &[u8]) -> bool {
-        self.screen.draw(x, y, ram)
+}
+
+// ncurses only draws Unicode if the locale says the terminal can take it.
+// (ncurses::setlocale hands C a dangling pointer, so call it directly.)
+#[cfg(feature = "ncurses")]
+fn set_locale() {
+    unsafe {
+        libc::setlocale(libc::LC_ALL, b"\0".as_ptr() as *const libc::c_char);
     }
 }
 
+// Draws a run of cells of the same color
+#[cfg(feature = "ncurses")]
+fn draw_run(y: usize, x: usize, run: &str, color: u8) {
+    nc::attron(nc::COLOR_PAIR(color as i16));
+    nc::mvaddstr(y as i32, x as i32, run);
+    nc::attroff(nc::COLOR_PAIR(color as i16));
+}
+
+#[cfg(feature = "ncurses")]
 use self::ncurses as nc;
 
+#[cfg(feature = "ncurses")]
 impl Render for NcursesDisplay {
     fn init(&self) {
-        /* Setup ncurses. */
+        /* Setup ncurses (with Unicode, for the packed cells). */
+        set_locale();
         nc::initscr();
         nc::raw();
 
@@ -197,31 +507,260 @@ impl Render for NcursesDisplay {
 
         /* Invisible cursor. */
         nc::curs_set(nc::CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+
+        /* Colors for XO-CHIP bitplanes (pair n is used for color n). */
+        if nc::has_colors() {
+            nc::start_color();
+            nc::init_pair(1, nc::COLOR_WHITE, nc::COLOR_BLACK);
+            nc::init_pair(2, nc::COLOR_RED, nc::COLOR_BLACK);
+            nc::init_pair(3, nc::COLOR_YELLOW, nc::COLOR_BLACK);
+        }
+
+        /* Key releases, where the terminal can tell (once on screen). */
+        nc::refresh();
+        tty::write(tty::KITTY_KEYS_ON);
+
+        self.last.borrow_mut().clear();
     }
     fn uninit(&self) {
         /* Kill ncurses. */
+        tty::write(tty::KITTY_KEYS_OFF);
         nc::endwin();
     }
 
-    fn render(&self, beep: bool) {
+    // ncurses can't be re-initialized after endwin(), but it can be resumed
+    fn suspend(&self) {
+        tty::write(tty::KITTY_KEYS_OFF);
+        nc::def_prog_mode();
+        nc::endwin();
+    }
+    fn resume(&self) {
+        nc::reset_prog_mode();
+        nc::refresh();
+        tty::write(tty::KITTY_KEYS_ON);
+    }
+
+    fn render(&self, screen: &ScreenRAM) {
+        let (w, h) = self.cells.size();
+        let (cols, rows) = (screen.width() / w, screen.height() / h);
+        let mut last = self.last.borrow_mut();
+
+        // wipe any leftovers from a bigger resolution
+        if self.last_res.get() != screen.resolution() || last.is_empty() {
+            self.last_res.set(screen.resolution());
+            nc::clear();
+            // never a real cell, so every cell gets drawn
+            *last = vec![('\0', 0); cols * rows];
+        }
+
+        let pixels = screen.pixels();
+        let mut run = self.run.borrow_mut();
+        for y in 0..rows {
+            let (mut start, mut color) = (0, 0);
+            for x in 0..cols + 1 {
+                // changed cells are drawn in runs, broken up by unchanged
+                // cells, or a change of color
+                let cell = if x < cols {
+                    Some(self.cells.glyph(screen, &pixels, x, y))
+                } else {
+                    None
+                };
+                let changed = cell.filter(|cell| *cell != last[y * cols + x]);
+
+                let same_run = changed.is_some_and(|(_, c)| c == color);
+                if !run.is_empty() && !same_run {
+                    draw_run(y, start, &run, color);
+                    run.clear();
+                }
+
+                if let Some((glyph, c)) = changed {
+                    if run.is_empty() {
+                        start = x;
+                        color = c;
+                    }
+                    run.push(glyph);
+                    last[y * cols + x] = (glyph, c);
+                }
+            }
+        }
+
+        nc::refresh();
+    }
+}
+
+/* ----------  ANSI Renderer  ---------- */
+// Renders with plain ANSI escape sequences, so it works without ncurses (in a
+// terminal that does 24-bit color).
+//
+// Each character cell is an upper half block, with the top pixel's color as
+// the foreground and the bottom pixel's as the background, so pixels come
+// out square, and every XO-CHIP color is shown as is. Like NcursesDisplay,
+// only the cells that changed since the last frame are redrawn.
+
+pub struct AnsiDisplay {
+    palette: Palette,
+    // the terminal settings to restore on exit
+    termios: Cell<Option<tty::Termios>>,
+
+    // the (top, bottom) pixels presented last frame, so only the cells that
+    // changed since need to be redrawn (empty if the whole screen does)
+    last: RefCell<Vec<(u8, u8)>>,
+    last_res: Cell<Resolution>,
+    // reused to build up each frame's output
+    out: RefCell<String>,
+}
+
+impl AnsiDisplay {
+    pub fn new(palette: Palette) -> AnsiDisplay {
+        AnsiDisplay {
+            palette,
+            termios: Cell::new(None),
+
+            last: RefCell::new(vec![]),
+            last_res: Cell::new(Resolution::Low),
+            out: RefCell::new(String::new()),
+        }
+    }
+}
+
+impl Render for AnsiDisplay {
+    fn init(&self) {
+        self.termios.set(tty::enter_fullscreen());
+        self.last.borrow_mut().clear();
+    }
+    fn uninit(&self) {
+        tty::leave_fullscreen(self.termios.take());
+    }
 
-        nc::mv(0, 0);
+    fn render(&self, screen: &ScreenRAM) {
+        let (cols, rows) = (screen.width(), screen.height() / 2);
+        let mut last = self.last.borrow_mut();
+        let mut out = self.out.borrow_mut();
+        out.clear();
+
+        // wipe any leftovers from a bigger resolution
+        if self.last_res.get() != screen.resolution() || last.is_empty() {
+            self.last_res.set(screen.resolution());
+            *out += "\x1b[2J";
+            // never a real cell, so every cell gets drawn
+            *last = vec![(0xFF, 0xFF); cols * rows];
+        }
+
+        // where the cursor is, and the colors it draws with, so escape
+        // sequences are only sent when they change
+        let mut cursor = None;
+        let mut colors = None;
+
+        let pixels = screen.pixels();
+        for y in 0..rows {
+            for x in 0..cols {
+                let top = pixels[(y * 2) * cols + x];
+                let bottom = pixels[(y * 2 + 1) * cols + x];
+                if last[y * cols + x] == (top, bottom) {
+                    continue;
+                }
+                last[y * cols + x] = (top, bottom);
+
+                if cursor != Some((y, x)) {
+                    *out += &format!("\x1b[{};{}H", y + 1, x + 1);
+                }
+                if colors != Some((top, bottom)) {
+                    let (fg, bg) = (self.palette[top as usize],
+                                    self.palette[bottom as usize]);
+                    *out += &format!("\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                                     fg[0], fg[1], fg[2],
+                                     bg[0], bg[1], bg[2]);
+                }
+                out.push('\u{2580}');
 
-        for y in 0..32 {
-            for x in 0..64 {
-                nc::printw(format!("{}",
-                                   self.screen.pixels.borrow()[y][x] as u8)
-                                   .replace("0", " ")
-                                   .replace("1", "X")
-                                   .as_ref());
+                cursor = Some((y, x + 1));
+                colors = Some((top, bottom));
             }
-            nc::printw("\n");
         }
 
-        if beep {
-            nc::beep();
+        if !out.is_empty() {
+            *out += "\x1b[0m";
+            tty::write(&out);
         }
+    }
+}
 
-        nc::refresh();
+/* ----------  Graphics Renderer  ---------- */
+// Draws the screen as a real image, in terminals that can show them (see
+// graphics.rs), so pixels come out crisp and square.
+//
+// Images are big, so one is only sent when the frame actually changed.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Graphics {
+    Sixel,
+    Kitty,
+}
+
+pub struct GraphicsDisplay {
+    graphics: Graphics,
+    palette: Palette,
+    // size of a lores pixel, in screen pixels (hires ones are half as big,
+    // rounded down, so the picture only keeps its size for even scales)
+    scale: usize,
+    // the terminal settings to restore on exit
+    termios: Cell<Option<tty::Termios>>,
+
+    // the pixels shown last frame (empty if the image needs redrawing)
+    last: RefCell<Vec<u8>>,
+    last_res: Cell<Resolution>,
+}
+
+impl GraphicsDisplay {
+    pub fn new(graphics: Graphics, palette: Palette, scale: usize)
+               -> GraphicsDisplay {
+        GraphicsDisplay {
+            graphics,
+            palette,
+            scale,
+            termios: Cell::new(None),
+
+            last: RefCell::new(vec![]),
+            last_res: Cell::new(Resolution::Low),
+        }
+    }
+}
+
+impl Render for GraphicsDisplay {
+    fn init(&self) {
+        self.termios.set(tty::enter_fullscreen());
+        self.last.borrow_mut().clear();
+    }
+    fn uninit(&self) {
+        if self.graphics == Graphics::Kitty {
+            tty::write(&graphics::kitty_delete());
+        }
+        tty::leave_fullscreen(self.termios.take());
+    }
+
+    fn render(&self, screen: &ScreenRAM) {
+        let pixels = screen.pixels();
+        let mut last = self.last.borrow_mut();
+        if self.last_res.get() == screen.resolution() && *last == *pixels {
+            return;
+        }
+
+        // top left, wiping any leftovers from a bigger resolution
+        let mut out = String::from("\x1b[H");
+        if self.last_res.get() != screen.resolution() {
+            self.last_res.set(screen.resolution());
+            out += "\x1b[2J";
+        }
+        last.clear();
+        last.extend_from_slice(&pixels);
+        drop(pixels);
+
+        let scale = (self.scale * Resolution::Low.width() / screen.width())
+            .max(1);
+        out += &match self.graphics {
+            Graphics::Sixel => graphics::sixel(screen, scale, &self.palette),
+            Graphics::Kitty => graphics::kitty(screen, scale, &self.palette),
+        };
+        tty::write(&out);
     }
 }
@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::fmt;
+
+// Everything that can go wrong while running a CHIP-8 program.
+//
+// Each error carries where it happened: the address of the instruction (pc),
+// and the instruction itself (opcode), so crashes can be reported in full.
+// Either can be missing, when the error didn't come from an instruction, or
+// the instruction couldn't be read.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmuError {
+    // The instruction doesn't exist (or isn't supported with these quirks)
+    InvalidOpcode { pc: Option<u16>, opcode: u16 },
+    // Memory was read / written past the end of RAM
+    OutOfBounds { pc: Option<u16>, opcode: Option<u16>, addr: u16 },
+    // 00EE with nothing to return to
+    StackUnderflow { pc: Option<u16>, opcode: Option<u16> },
+    // 2nnn with the stack already full
+    StackOverflow { pc: Option<u16>, opcode: Option<u16> },
+    // A sprite was read from past the end of RAM (addr is I)
+    IOverflow { pc: Option<u16>, opcode: Option<u16>, addr: u16 },
+    // Fx29 / Fx30 for a digit with no font sprite
+    BadFontDigit { pc: Option<u16>, opcode: Option<u16>, digit: u8 },
+}
+
+impl EmuError {
+    // Errors raised outside the CPU (e.g. by RAM) don't know which
+    // instruction caused them, so the CPU fills it in on the way out (there's
+    // no opcode when it's the instruction fetch itself that failed)
+    pub fn at(self, pc: u16, opcode: Option<u16>) -> EmuError {
+        use self::EmuError::*;
+
+        let pc = Some(pc);
+        match self {
+            InvalidOpcode { opcode, .. } => InvalidOpcode { pc, opcode },
+            OutOfBounds { addr, .. } => OutOfBounds { pc, opcode, addr },
+            StackUnderflow { .. } => StackUnderflow { pc, opcode },
+            StackOverflow { .. } => StackOverflow { pc, opcode },
+            IOverflow { addr, .. } => IOverflow { pc, opcode, addr },
+            BadFontDigit { digit, .. } => BadFontDigit { pc, opcode, digit },
+        }
+    }
+
+    pub fn pc(&self) -> Option<u16> {
+        use self::EmuError::*;
+
+        match *self {
+            InvalidOpcode { pc, .. } |
+            OutOfBounds { pc, .. } |
+            StackUnderflow { pc, .. } |
+            StackOverflow { pc, .. } |
+            IOverflow { pc, .. } |
+            BadFontDigit { pc, .. } => pc,
+        }
+    }
+
+    pub fn opcode(&self) -> Option<u16> {
+        use self::EmuError::*;
+
+        match *self {
+            InvalidOpcode { opcode, .. } => Some(opcode),
+            OutOfBounds { opcode, .. } |
+            StackUnderflow { opcode, .. } |
+            StackOverflow { opcode, .. } |
+            IOverflow { opcode, .. } |
+            BadFontDigit { opcode, .. } => opcode,
+        }
+    }
+
+    // The memory address involved, if any
+    pub fn addr(&self) -> Option<u16> {
+        match *self {
+            EmuError::OutOfBounds { addr, .. } |
+            EmuError::IOverflow { addr, .. } => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmuError::InvalidOpcode { opcode, .. } => {
+                write!(f, "[CPU] Invalid Opcode - {:04x}", opcode)?
+            }
+            EmuError::OutOfBounds { addr, .. } => {
+                write!(f, "[RAM] Address 0x{:03x} is out of bounds!", addr)?
+            }
+            EmuError::StackUnderflow { .. } => {
+                write!(f, "[CPU] Cannot RET when stack is empty!")?
+            }
+            EmuError::StackOverflow { .. } => {
+                write!(f, "[CPU] Cannot CALL when stack is full!")?
+            }
+            EmuError::IOverflow { addr, .. } => {
+                write!(f, "[CPU] Sprite at I = 0x{:03x} overflows RAM", addr)?
+            }
+            EmuError::BadFontDigit { digit, .. } => {
+                write!(f, "[CPU] No font sprite for digit 0x{:02x}", digit)?
+            }
+        }
+
+        match (self.pc(), self.opcode()) {
+            (Some(pc), Some(opcode)) => {
+                write!(f, " (at 0x{:03x}: {:04x})", pc, opcode)
+            }
+            (Some(pc), None) => write!(f, " (at 0x{:03x})", pc),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Error for EmuError {}
@@ -0,0 +1,94 @@
+use display;
+use input;
+use sound;
+
+// A Frontend bundles together everything the emulator needs to talk to the
+// outside world: a way to show the screen, a way to read the keypad, and a
+// way to make noise.
+//
+// Frontends are picked at runtime by name, so each part is a trait object.
+
+pub struct Frontend {
+    pub display: Box<dyn display::Render>,
+    pub input: Box<dyn input::Input>,
+    pub sound: Box<dyn sound::Play>,
+}
+
+// The ncurses ones can be left out (see the `ncurses` feature in Cargo.toml)
+#[cfg(feature = "ncurses")]
+pub const FRONTENDS: &[&str] = &["ncurses", "halfblock", "braille", "ansi",
+                                 "sixel", "kitty", "term", "null"];
+#[cfg(not(feature = "ncurses"))]
+pub const FRONTENDS: &[&str] = &["ansi", "sixel", "kitty", "term", "null"];
+
+// The first one is the default
+pub const DEFAULT_FRONTEND: &str = FRONTENDS[0];
+
+impl Frontend {
+    // The palette / scale are for the frontends that use them
+    pub fn from_name(name: &str, palette: display::Palette, scale: usize)
+                     -> Option<Frontend> {
+        match name {
+            // Default, interactive frontend
+            #[cfg(feature = "ncurses")]
+            "ncurses" => Some(Frontend {
+                display: Box::new(display::NcursesDisplay::new()),
+                input: Box::new(input::NcursesInput::new()),
+                sound: Box::new(sound::NcursesBeep::new()),
+            }),
+            // Like ncurses, but packing 1x2 / 2x4 pixels into each
+            // character, using Unicode half blocks / Braille patterns
+            #[cfg(feature = "ncurses")]
+            "halfblock" => Some(Frontend {
+                display: Box::new(display::NcursesDisplay::with_cells(
+                    display::Cells::HalfBlock)),
+                input: Box::new(input::NcursesInput::new()),
+                sound: Box::new(sound::NcursesBeep::new()),
+            }),
+            #[cfg(feature = "ncurses")]
+            "braille" => Some(Frontend {
+                display: Box::new(display::NcursesDisplay::with_cells(
+                    display::Cells::Braille)),
+                input: Box::new(input::NcursesInput::new()),
+                sound: Box::new(sound::NcursesBeep::new()),
+            }),
+            // Interactive like ncurses, but doesn't need it installed
+            "ansi" => Some(Frontend {
+                display: Box::new(display::AnsiDisplay::new(palette)),
+                input: Box::new(input::RawInput::new()),
+                sound: Box::new(sound::TermBell::new()),
+            }),
+            // Like ansi, but drawing real images, with Sixel graphics / the
+            // kitty graphics protocol
+            "sixel" => Some(Frontend {
+                display: Box::new(display::GraphicsDisplay::new(
+                    display::Graphics::Sixel, palette, scale)),
+                input: Box::new(input::RawInput::new()),
+                sound: Box::new(sound::TermBell::new()),
+            }),
+            "kitty" => Some(Frontend {
+                display: Box::new(display::GraphicsDisplay::new(
+                    display::Graphics::Kitty, palette, scale)),
+                input: Box::new(input::RawInput::new()),
+                sound: Box::new(sound::TermBell::new()),
+            }),
+            // Prints frames line-by-line, without any input
+            "term" => Some(Frontend {
+                display: Box::new(display::TermDisplay::new()),
+                input: Box::new(input::NullInput::new()),
+                sound: Box::new(sound::TermBell::new()),
+            }),
+            "null" => Some(Frontend::null()),
+            _ => None,
+        }
+    }
+
+    // Does nothing, only used for testing
+    pub fn null() -> Frontend {
+        Frontend {
+            display: Box::new(display::NullDisplay::new()),
+            input: Box::new(input::NullInput::new()),
+            sound: Box::new(sound::NullSound::new()),
+        }
+    }
+}
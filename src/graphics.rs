@@ -0,0 +1,174 @@
+use std::str;
+
+use display::Palette;
+use display::ScreenRAM;
+
+// Encodes the screen as an image, for terminals that can show real bitmaps:
+//
+//   Sixel - the DEC format, supported by xterm (-ti vt340), foot, mlterm,
+//           WezTerm, and others
+//   kitty - the kitty graphics protocol [1], also supported by WezTerm,
+//           Ghostty, and others
+//
+// Each CHIP-8 pixel is drawn as a `scale` x `scale` square.
+//
+// [1]: https://sw.kovidgoyal.net/kitty/graphics-protocol/
+
+// Id of the one image sent with the kitty protocol, so each frame replaces
+// the last, instead of piling up in the terminal
+const KITTY_IMAGE_ID: u32 = 1;
+
+// Max bytes of image data per kitty protocol escape code
+const KITTY_CHUNK: usize = 4096;
+
+/* ----------  Sixel  ---------- */
+
+pub fn sixel(screen: &ScreenRAM, scale: usize, palette: &Palette) -> String {
+    let (width, height) = (screen.width(), screen.height() * scale);
+    let pixels = screen.pixels();
+
+    // square pixels, and the size of the image
+    let mut out = format!("\x1bPq\"1;1;{};{}", width * scale, height);
+    for (i, rgb) in palette.iter().enumerate() {
+        // sixel colors are in percent
+        let pct = |c: u8| c as u32 * 100 / 255;
+        out += &format!("#{};2;{};{};{}", i, pct(rgb[0]), pct(rgb[1]),
+                        pct(rgb[2]));
+    }
+
+    // The image is drawn in bands, 6 pixels tall, one color at a time. Each
+    // character is a column of the band, with a bit set for each pixel of
+    // the current color. Every pixel is drawn, even the unlit ones, so the
+    // last frame is painted over entirely.
+    let mut band = vec![0; width];
+    for top in (0..height).step_by(6) {
+        for color in 0..palette.len() {
+            for (x, bits) in band.iter_mut().enumerate() {
+                *bits = 0;
+                for dy in 0..6.min(height - top) {
+                    let y = (top + dy) / scale;
+                    if pixels[y * width + x] as usize == color {
+                        *bits |= 1 << dy;
+                    }
+                }
+            }
+            if band.iter().all(|bits| *bits == 0) {
+                continue;
+            }
+
+            out += &format!("#{}", color);
+            let mut x = 0;
+            while x < width {
+                let bits = band[x];
+                let run = band[x..].iter().take_while(|b| **b == bits).count();
+                push_sixels(&mut out, 0x3F + bits, run * scale);
+                x += run;
+            }
+            out.push('$'); // back to the start of the band
+        }
+        out.push('-'); // on to the next band
+    }
+
+    out += "\x1b\\";
+    out
+}
+
+// Appends `n` copies of a sixel, run length encoded if it's shorter
+fn push_sixels(out: &mut String, sixel: u8, n: usize) {
+    if n > 3 {
+        *out += &format!("!{}{}", n, sixel as char);
+    } else {
+        out.extend((0..n).map(|_| sixel as char));
+    }
+}
+
+/* ----------  kitty  ---------- */
+
+pub fn kitty(screen: &ScreenRAM, scale: usize, palette: &Palette) -> String {
+    let (width, height) = (screen.width(), screen.height());
+    let pixels = screen.pixels();
+
+    // plain 24-bit RGB, with each row (and each pixel in it) repeated
+    let mut rgb = Vec::with_capacity(width * height * scale * scale * 3);
+    let mut row = Vec::with_capacity(width * scale * 3);
+    for y in 0..height {
+        row.clear();
+        for &pixel in &pixels[y * width..(y + 1) * width] {
+            for _ in 0..scale {
+                row.extend_from_slice(&palette[pixel as usize]);
+            }
+        }
+        for _ in 0..scale {
+            rgb.extend_from_slice(&row);
+        }
+    }
+
+    // Sent in chunks, with m=1 on all but the last. The first one says what
+    // to do: transmit and show the image (replacing the last one), without
+    // moving the cursor, and without replying.
+    let data = base64(&rgb);
+    let chunks = data.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+    let mut out = String::with_capacity(data.len() + chunks.len() * 16);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            out += &format!("\x1b_Ga=T,f=24,s={},v={},i={},p=1,C=1,q=2,m={};",
+                            width * scale,
+                            height * scale,
+                            KITTY_IMAGE_ID,
+                            more);
+        } else {
+            out += &format!("\x1b_Gm={};", more);
+        }
+        out += str::from_utf8(chunk).unwrap(); // base64 is ASCII
+        out += "\x1b\\";
+    }
+    out
+}
+
+// Deletes the image (and frees its memory in the terminal)
+pub fn kitty_delete() -> String {
+    format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", KITTY_IMAGE_ID)
+}
+
+const BASE64: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let byte = |i: usize| *chunk.get(i).unwrap_or(&0) as u32;
+        let bits = byte(0) << 16 | byte(1) << 8 | byte(2);
+
+        // 4 characters for every 3 bytes, padded with '='
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (bits >> (18 - 6 * i)) & 0x3F;
+                out.push(BASE64[sextet as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base64;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn base64_all_sextets() {
+        assert_eq!(base64(&[0x00, 0x10, 0x83, 0x10, 0x51, 0x87]), "ABCDEFGH");
+        assert_eq!(base64(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+}
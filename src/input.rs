@@ -1,27 +1,89 @@
 #![allow(dead_code, unused_variables)]
 
+#[cfg(feature = "ncurses")]
 extern crate ncurses;
 
 use std::cell::Cell;
 use std::cell::RefCell;
 use std::char;
+use std::fs::File;
+use std::io;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::str;
+
+use savestate;
+use tty;
 
 /*====================================
 =            Input Traits            =
 ====================================*/
 
+// Emulator controls, as opposed to CHIP-8 keypad keys
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    Pause,
+    Debug,         // break into the debugger
+    Rewind,        // step back one frame (sent repeatedly while held)
+    SaveState(u8), // save to numbered slot
+    LoadState(u8), // load from numbered slot
+}
+
+// The keypad state that is visible to the CPU (part of save states)
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Latch {
+    pub keys: [u8; 16],
+    pub last_press: Option<u8>,
+}
+
+impl Latch {
+    pub fn save_state(&self, w: &mut savestate::Writer) {
+        w.bytes(&self.keys);
+        w.u8(self.last_press.unwrap_or(0xFF));
+    }
+
+    pub fn load_state(r: &mut savestate::Reader) -> Result<Latch, String> {
+        let mut latch = Latch::default();
+        r.array(&mut latch.keys)?;
+        latch.last_press = match r.u8()? {
+            0xFF => None,
+            key => Some(key),
+        };
+        Ok(latch)
+    }
+}
+
 pub trait Get {
     fn pressed_esc(&self) -> bool; // Checks if exit key was pressed
+    fn hotkey(&self) -> Option<Hotkey>; // Takes the last hotkey pressed
 
     fn pressed_key(&self, key: u8) -> bool; // Checks if key 0-F was pressed
     fn last_press(&self) -> Option<u8>;
+
+    fn latch(&self) -> Latch; // Snapshot of the keypad state
 }
 
 pub trait Set {
     fn decrement_keys(&self);
     fn update_keys(&self, block: bool); // updates key list with pressed keys
+
+    fn restore_latch(&self, latch: &Latch); // Restore a keypad snapshot
+
+    // let go of every held key, for when releases may have been missed (eg:
+    // while the terminal was handed over to the debugger)
+    fn release_keys(&self) {}
+
+    // the machine went back `frames` frames (and the keypad was restored
+    // with `restore_latch`), so forget whatever happened since
+    fn rewind(&self, frames: u64) {}
 }
 
+// Both halves of an input method, so it can be boxed up in a Frontend
+pub trait Input: Get + Set {}
+
+impl<T: Get + Set> Input for T {}
+
 /*=====================================
 =            Input Methods            =
 =====================================*/
@@ -40,6 +102,9 @@ impl Get for NullInput {
     fn pressed_esc(&self) -> bool {
         false
     }
+    fn hotkey(&self) -> Option<Hotkey> {
+        None
+    }
 
     fn pressed_key(&self, key: u8) -> bool {
         false
@@ -47,36 +112,579 @@ impl Get for NullInput {
     fn last_press(&self) -> Option<u8> {
         None
     }
+
+    fn latch(&self) -> Latch {
+        Latch::default()
+    }
 }
 
 impl Set for NullInput {
     fn decrement_keys(&self) {}
     fn update_keys(&self, block: bool) {}
+
+    fn restore_latch(&self, latch: &Latch) {}
 }
 
-/* ----------  Ncurses Input  ---------- */
+/* ----------  Scripted Input  ---------- */
+// Replays key presses from a script, for running ROMs without a human.
+//
+// Each line of the script is a frame number, followed by the keys (in hex)
+// that are held down from that frame onwards, or `-` to release them all:
+//
+//   # frame  keys
+//   60       5       hold 5 from the 60th frame...
+//   90       -       ...until the 90th
+//   120      4 6
+//
+// Frames are counted with `decrement_keys`, which is called once a frame.
+// Movies made with RecordingInput use the same format.
+
+// Sets the held keys, and remembers which of them were just pressed
+fn press_keys(keys: &RefCell<[u8; 16]>,
+              last_press: &Cell<Option<u8>>,
+              held: [bool; 16]) {
+    let mut keys = keys.borrow_mut();
+    for key in 0..16 {
+        if held[key] && keys[key] == 0 {
+            last_press.set(Some(key as u8));
+        }
+        keys[key] = held[key] as u8;
+    }
+}
+
+pub struct ScriptedInput {
+    events: Vec<(u64, [bool; 16])>, // sorted by frame
+    frame: Cell<u64>,
+    next: Cell<usize>, // next event to apply
 
-pub struct NcursesInput {
+    keys: RefCell<[u8; 16]>,
+    last_press: Cell<Option<u8>>,
+}
+
+impl ScriptedInput {
+    pub fn parse(script: &str) -> Result<ScriptedInput, String> {
+        let mut events = Vec::new();
+
+        for (n, line) in script.lines().enumerate() {
+            let err = |what: &str| {
+                format!("[Input] Line {} of input script: {}", n + 1, what)
+            };
+
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let frame = match words.next() {
+                Some(frame) => frame.parse::<u64>()
+                    .map_err(|_| err("invalid frame number"))?,
+                None => continue, // blank line
+            };
+
+            let mut held = [false; 16];
+            for word in words {
+                if word == "-" {
+                    continue;
+                }
+                match u8::from_str_radix(word, 16) {
+                    Ok(key) if key <= 0xF => held[key as usize] = true,
+                    _ => return Err(err("keys must be 0-F")),
+                }
+            }
+
+            events.push((frame, held));
+        }
+
+        // stable, so later lines for the same frame win
+        events.sort_by_key(|&(frame, _)| frame);
+
+        let scripted = ScriptedInput {
+            events,
+            frame: Cell::new(0),
+            next: Cell::new(0),
+
+            keys: RefCell::new([0; 16]),
+            last_press: Cell::new(None),
+        };
+        scripted.apply_events();
+        Ok(scripted)
+    }
+
+    // Apply every event that is due. Events only ever take effect at the
+    // start of a frame, just like while recording.
+    fn apply_events(&self) {
+        while let Some(&(frame, held)) = self.events.get(self.next.get()) {
+            if frame > self.frame.get() {
+                break;
+            }
+            self.next.set(self.next.get() + 1);
+            press_keys(&self.keys, &self.last_press, held);
+        }
+    }
+}
+
+impl Get for ScriptedInput {
+    fn pressed_esc(&self) -> bool {
+        false
+    }
+    fn hotkey(&self) -> Option<Hotkey> {
+        None
+    }
+
+    fn pressed_key(&self, key: u8) -> bool {
+        if key > 0xF {
+            return false;
+        }
+
+        self.keys.borrow()[key as usize] != 0
+    }
+
+    fn last_press(&self) -> Option<u8> {
+        self.last_press.take()
+    }
+
+    fn latch(&self) -> Latch {
+        Latch {
+            keys: *self.keys.borrow(),
+            last_press: self.last_press.get(),
+        }
+    }
+}
+
+impl Set for ScriptedInput {
+    fn decrement_keys(&self) {
+        self.frame.set(self.frame.get() + 1);
+        self.apply_events();
+    }
+
+    fn update_keys(&self, block: bool) {}
+
+    fn restore_latch(&self, latch: &Latch) {
+        *self.keys.borrow_mut() = latch.keys;
+        self.last_press.set(latch.last_press);
+    }
+
+    fn rewind(&self, frames: u64) {
+        let frame = self.frame.get().saturating_sub(frames);
+        self.frame.set(frame);
+        let next = self.events
+            .iter()
+            .position(|&(at, _)| at > frame)
+            .unwrap_or(self.events.len());
+        self.next.set(next);
+    }
+}
+
+/* ----------  Recording Input  ---------- */
+// Wraps another input method, and records every change to the keypad into a
+// movie file, which can be played back with ScriptedInput.
+//
+// To make playback exact, the CPU only gets to see the keypad as it was at
+// the start of each frame, and waiting for a key press never blocks (the
+// CPU just spins on Fx0A until the next frame, same as during playback).
+//
+// Rewinding cuts the movie back to the frame rewound to, so it only ever
+// holds the timeline that was kept.
+
+pub struct RecordingInput {
+    inner: Box<dyn Input>,
+    movie: RefCell<File>,
+    frame: Cell<u64>,
+    lines: RefCell<Vec<(u64, u64)>>, // (frame, file offset) of each line
+
+    keys: RefCell<[u8; 16]>,
+    last_press: Cell<Option<u8>>,
+}
+
+impl RecordingInput {
+    pub fn create(inner: Box<dyn Input>,
+                  path: &str,
+                  seed: u64)
+                  -> Result<RecordingInput, String> {
+        let err = |e: io::Error| {
+            format!("[Input] Couldn't write movie '{}': {}", path, e)
+        };
+
+        let mut movie = File::create(path).map_err(err)?;
+        write!(movie, "# ac8e movie\n# seed {}\n# frame  keys\n", seed)
+            .map_err(err)?;
+
+        Ok(RecordingInput {
+            inner,
+            movie: RefCell::new(movie),
+            frame: Cell::new(0),
+            lines: RefCell::new(Vec::new()),
+
+            keys: RefCell::new([0; 16]),
+            last_press: Cell::new(None),
+        })
+    }
+}
+
+// Reads the seed back out of a movie's header
+pub fn movie_seed(movie: &str) -> Option<u64> {
+    movie.lines()
+        .filter_map(|line| line.strip_prefix("# seed "))
+        .filter_map(|seed| seed.trim().parse().ok())
+        .next()
+}
+
+impl Get for RecordingInput {
+    fn pressed_esc(&self) -> bool {
+        self.inner.pressed_esc()
+    }
+    fn hotkey(&self) -> Option<Hotkey> {
+        self.inner.hotkey()
+    }
+
+    fn pressed_key(&self, key: u8) -> bool {
+        if key > 0xF {
+            return false;
+        }
+
+        self.keys.borrow()[key as usize] != 0
+    }
+
+    fn last_press(&self) -> Option<u8> {
+        self.last_press.take()
+    }
+
+    fn latch(&self) -> Latch {
+        Latch {
+            keys: *self.keys.borrow(),
+            last_press: self.last_press.get(),
+        }
+    }
+}
+
+impl Set for RecordingInput {
+    fn decrement_keys(&self) {
+        self.inner.decrement_keys();
+        self.frame.set(self.frame.get() + 1);
+
+        // latch the keypad for the next frame
+        let mut held = [false; 16];
+        for (key, held) in held.iter_mut().enumerate() {
+            *held = self.inner.pressed_key(key as u8);
+        }
+        self.inner.last_press(); // derived from `held` instead
+
+        let keys = *self.keys.borrow();
+        if (0..16).all(|key| held[key] == (keys[key] != 0)) {
+            return;
+        }
+
+        press_keys(&self.keys, &self.last_press, held);
+
+        let keys = (0..16)
+            .filter(|key| held[*key])
+            .map(|key| format!("{:X}", key))
+            .collect::<Vec<String>>();
+        let keys = if keys.is_empty() {
+            "-".to_string()
+        } else {
+            keys.join(" ")
+        };
+
+        // not much to be done about a failed write, keep on playing
+        let mut movie = self.movie.borrow_mut();
+        if let Ok(offset) = movie.stream_position() {
+            self.lines.borrow_mut().push((self.frame.get(), offset));
+        }
+        writeln!(movie, "{:<8} {}", self.frame.get(), keys).ok();
+    }
+
+    fn update_keys(&self, block: bool) {
+        self.inner.update_keys(false);
+    }
+
+    fn release_keys(&self) {
+        self.inner.release_keys();
+    }
+
+    fn restore_latch(&self, latch: &Latch) {
+        *self.keys.borrow_mut() = latch.keys;
+        self.last_press.set(latch.last_press);
+    }
+
+    fn rewind(&self, frames: u64) {
+        self.inner.rewind(frames);
+
+        let frame = self.frame.get().saturating_sub(frames);
+        self.frame.set(frame);
+
+        // drop the lines for the frames that were undone
+        let mut lines = self.lines.borrow_mut();
+        if let Some(n) = lines.iter().position(|&(at, _)| at > frame) {
+            let offset = lines[n].1;
+            lines.truncate(n);
+
+            let mut movie = self.movie.borrow_mut();
+            movie.set_len(offset)
+                .and_then(|_| movie.seek(SeekFrom::Start(offset)))
+                .ok();
+        }
+    }
+}
+
+/* ----------  Terminal Keyboard  ---------- */
+// What the terminal-based input methods have in common: the keymap, and
+// making sense of what the terminal sends when keys are pressed.
+//
+// Terminals normally only send key presses (and repeats, which look just
+// the same), so a pressed key is held for a few frames, and keeps being held
+// as long as the terminal repeats it.
+//
+// Terminals that speak the kitty keyboard protocol [1] can send real press,
+// repeat and release events instead. The renderer asks for them (see
+// tty::KITTY_KEYS_ON), along with whether the protocol is supported, and
+// once the terminal answers, keys are held for exactly as long as they are
+// down.
+//
+// [1]: https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+
+// The keypad is mapped onto the left side of a QWERTY keyboard
+fn keypad_key(c: char) -> Option<u8> {
+    #[rustfmt::skip] // keep the 4x4
+    let key = match c {
+        '1' => 0x1, '2' => 0x2, '3' => 0x3, '4' => 0xC,
+        'q' => 0x4, 'w' => 0x5, 'e' => 0x6, 'r' => 0xD,
+        'a' => 0x7, 's' => 0x8, 'd' => 0x9, 'f' => 0xE,
+        'z' => 0xA, 'x' => 0x0, 'c' => 0xB, 'v' => 0xF,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// The hotkey for function key Fn (F1, which exits, is handled separately)
+//
+// Save states: F5 saves, F9 loads, F6 / F7 pick the slot (0-9)
+fn function_key(n: u8, slot: &Cell<u8>) -> Option<Hotkey> {
+    match n {
+        2 => Some(Hotkey::Pause),
+        3 => Some(Hotkey::Debug),
+        4 => Some(Hotkey::Rewind),
+        5 => Some(Hotkey::SaveState(slot.get())),
+        9 => Some(Hotkey::LoadState(slot.get())),
+        6 => {
+            slot.set((slot.get() + 9) % 10);
+            None
+        }
+        7 => {
+            slot.set((slot.get() + 1) % 10);
+            None
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum KeyEvent {
+    Press,
+    Repeat,
+    Release, // kitty protocol only
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TermKey {
+    Char(char, KeyEvent),
+    Function(u8, KeyEvent), // F1 - F12
+    Interrupt,              // Ctrl-C
+    KittyFlags,             // the terminal will send key releases
+}
+
+// Splits a chunk of terminal input into keys. Unknown escape sequences are
+// skipped whole, so they don't turn into stray key presses.
+//
+// Also returns how many bytes were used up: an escape sequence that is cut
+// off at the end (split across reads) is left over, to be parsed again once
+// the rest of it arrives.
+fn parse_term_keys(bytes: &[u8]) -> (Vec<TermKey>, usize) {
+    let mut keys = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        i += 1;
+
+        match rest {
+            [0x03, ..] => keys.push(TermKey::Interrupt),
+            // the start of one of the sequences below, cut off
+            [0x1B] | [0x1B, b'O'] | [0x1B, b'[', b'['] => {
+                i -= 1;
+                break;
+            }
+            // ESC O P - ESC O S: F1 - F4 (xterm)
+            [0x1B, b'O', c @ b'P'..=b'S', ..] => {
+                keys.push(TermKey::Function(c - b'P' + 1, KeyEvent::Press));
+                i += 2;
+            }
+            // ESC [ [ A - ESC [ [ E: F1 - F5 (Linux console)
+            [0x1B, b'[', b'[', c @ b'A'..=b'E', ..] => {
+                keys.push(TermKey::Function(c - b'A' + 1, KeyEvent::Press));
+                i += 3;
+            }
+            // ESC [ <params> <final byte>
+            [0x1B, b'[', params @ ..] => {
+                let end = match params.iter()
+                    .position(|b| (0x40..=0x7E).contains(b)) {
+                    Some(end) => end,
+                    None => {
+                        // cut off
+                        i -= 1;
+                        break;
+                    }
+                };
+                if let Some(key) = parse_csi(&params[..end], params[end]) {
+                    keys.push(key);
+                }
+                i += end + 2;
+            }
+            // a lone ESC, or Alt + key, which is just the key
+            [0x1B, ..] => (),
+            [c, ..] => keys.push(TermKey::Char(*c as char, KeyEvent::Press)),
+            [] => unreachable!(),
+        }
+    }
+
+    (keys, i)
+}
+
+// The key for `ESC [ <params> <final>`, if it is one. The params are
+// `<code>;<modifiers>:<event>` (all optional), where code is the key's
+// Unicode codepoint in the kitty protocol (with final byte 'u').
+fn parse_csi(params: &[u8], last: u8) -> Option<TermKey> {
+    // ESC [ ? <flags> u: answer to the kitty protocol query. Releases are
+    // only sent if the terminal took flag 2 (report event types).
+    if let Some(flags) = params.strip_prefix(b"?") {
+        let flags = str::from_utf8(flags).ok()?.parse::<u32>().ok()?;
+        return match last {
+            b'u' if flags & 2 != 0 => Some(TermKey::KittyFlags),
+            _ => None,
+        };
+    }
+
+    let params = str::from_utf8(params).ok()?;
+    let fields = params.split(';')
+        .map(|field| {
+            field.split(':')
+                .map(|n| n.parse::<u32>().unwrap_or(0))
+                .collect::<Vec<u32>>()
+        })
+        .collect::<Vec<Vec<u32>>>();
+    let field = |n: usize, sub: usize| fields.get(n)?.get(sub).cloned();
+
+    let code = field(0, 0).unwrap_or(0);
+    let modifiers = field(1, 0).unwrap_or(1).saturating_sub(1);
+    let event = match field(1, 1) {
+        Some(2) => KeyEvent::Repeat,
+        Some(3) => KeyEvent::Release,
+        _ => KeyEvent::Press,
+    };
+    let ctrl = modifiers & 0x4 != 0;
+
+    match (code, last) {
+        // ESC [ 99 ; 5 u: Ctrl-C, as sent in the kitty protocol
+        (99, b'u') if ctrl => match event {
+            KeyEvent::Press => Some(TermKey::Interrupt),
+            _ => None,
+        },
+        (_, b'u') => Some(TermKey::Char(char::from_u32(code)?, event)),
+        // ESC [ 1 ; <mod> P - S: F1 - F4 with modifiers / events
+        (0..=1, b'P'..=b'S') => Some(TermKey::Function(last - b'P' + 1, event)),
+        // ESC [ <code> ~: F1 - F12 (VT220), skipping 16 and 22
+        (11..=15, b'~') => Some(TermKey::Function(code as u8 - 10, event)),
+        (17..=21, b'~') => Some(TermKey::Function(code as u8 - 11, event)),
+        (23..=24, b'~') => Some(TermKey::Function(code as u8 - 12, event)),
+        _ => None,
+    }
+}
+
+// Keypad / hotkey state, fed with the keys read from the terminal
+struct Keyboard {
     keys: RefCell<[u8; 16]>,
     exit: Cell<bool>,
+    hotkey: Cell<Option<Hotkey>>,
+    slot: Cell<u8>, // selected save state slot
+    // the terminal sends key releases (kitty protocol)
+    exact: Cell<bool>,
+    // the start of an escape sequence, still waiting on the rest of it
+    pending: RefCell<Vec<u8>>,
 
     last_press: Cell<Option<u8>>,
 }
 
-impl NcursesInput {
-    pub fn new() -> NcursesInput {
-        NcursesInput {
+// Longest escape sequence worth waiting on the rest of
+const MAX_PENDING: usize = 32;
+
+impl Keyboard {
+    fn new() -> Keyboard {
+        Keyboard {
             keys: RefCell::new([0; 16]),
             exit: Cell::new(false),
+            hotkey: Cell::new(None),
+            slot: Cell::new(0),
+            exact: Cell::new(false),
+            pending: RefCell::new(Vec::new()),
 
             last_press: Cell::new(None),
         }
     }
-}
 
-impl Get for NcursesInput {
-    fn pressed_esc(&self) -> bool {
-        self.exit.get()
+    // Handles the keys in a chunk of terminal input (along with whatever
+    // was left over from the last one)
+    fn feed(&self, bytes: &[u8]) {
+        let mut pending = self.pending.borrow_mut();
+        pending.extend_from_slice(bytes);
+
+        let (keys, used) = parse_term_keys(&pending);
+        pending.drain(..used);
+        if pending.len() > MAX_PENDING {
+            // not going to end, it's just garbage
+            pending.clear();
+        }
+
+        for key in keys {
+            self.handle(key);
+        }
+    }
+
+    fn handle(&self, key: TermKey) {
+        match key {
+            TermKey::KittyFlags => self.exact.set(true),
+            // Exit Keys
+            TermKey::Function(1, KeyEvent::Press) | TermKey::Interrupt => {
+                self.exit.set(true)
+            }
+            // Hotkeys (repeated while held, for rewind)
+            TermKey::Function(_, KeyEvent::Release) => (),
+            TermKey::Function(n, _) => {
+                if let Some(hotkey) = function_key(n, &self.slot) {
+                    self.hotkey.set(Some(hotkey));
+                }
+            }
+            // Keymap
+            TermKey::Char(c, event) => {
+                if let Some(key) = keypad_key(c) {
+                    self.key_event(key, event);
+                }
+            }
+        }
+    }
+
+    fn key_event(&self, key: u8, event: KeyEvent) {
+        let mut keys = self.keys.borrow_mut();
+        match event {
+            KeyEvent::Press => {
+                // Recall that this key was just pressed
+                self.last_press.set(Some(key));
+                // This number controls "key stickieness"
+                keys[key as usize] = 8;
+            }
+            KeyEvent::Repeat => keys[key as usize] = 8,
+            KeyEvent::Release => {
+                // only ever sent by terminals that speak the protocol
+                self.exact.set(true);
+                keys[key as usize] = 0;
+            }
+        }
     }
 
     fn pressed_key(&self, key: u8) -> bool {
@@ -87,59 +695,301 @@ impl Get for NcursesInput {
         self.keys.borrow()[key as usize] != 0
     }
 
+    fn latch(&self) -> Latch {
+        Latch {
+            keys: *self.keys.borrow(),
+            last_press: self.last_press.get(),
+        }
+    }
+
+    fn decrement_keys(&self) {
+        // keys are let go of when they're released
+        if self.exact.get() {
+            return;
+        }
+
+        for x in self.keys.borrow_mut().iter_mut() {
+            *x -= if *x > 0 { 1 } else { 0 }
+        }
+    }
+
+    fn restore_latch(&self, latch: &Latch) {
+        // keys that are held down stay that way until they're released, no
+        // matter what the snapshot says
+        if !self.exact.get() {
+            *self.keys.borrow_mut() = latch.keys;
+        }
+        self.last_press.set(latch.last_press);
+    }
+
+    fn release_keys(&self) {
+        *self.keys.borrow_mut() = [0; 16];
+    }
+}
+
+/* ----------  Ncurses Input  ---------- */
+
+#[cfg(feature = "ncurses")]
+pub struct NcursesInput {
+    keyboard: Keyboard,
+}
+
+#[cfg(feature = "ncurses")]
+impl NcursesInput {
+    pub fn new() -> NcursesInput {
+        NcursesInput { keyboard: Keyboard::new() }
+    }
+}
+
+#[cfg(feature = "ncurses")]
+impl Get for NcursesInput {
+    fn pressed_esc(&self) -> bool {
+        self.keyboard.exit.get()
+    }
+    fn hotkey(&self) -> Option<Hotkey> {
+        self.keyboard.hotkey.take()
+    }
+
+    fn pressed_key(&self, key: u8) -> bool {
+        self.keyboard.pressed_key(key)
+    }
+
     fn last_press(&self) -> Option<u8> {
         // Give back the key just pressed, and set self to None
-        let last_press = self.last_press.get();
-        self.last_press.set(None);
-        last_press
+        self.keyboard.last_press.take()
+    }
+
+    fn latch(&self) -> Latch {
+        self.keyboard.latch()
     }
 }
 
+#[cfg(feature = "ncurses")]
 use self::ncurses as nc;
 
+#[cfg(feature = "ncurses")]
 impl Set for NcursesInput {
     fn decrement_keys(&self) {
-        for x in self.keys.borrow_mut().iter_mut() {
-            *x -= if *x > 0 { 1 } else { 0 }
-        }
+        self.keyboard.decrement_keys();
+    }
+
+    fn restore_latch(&self, latch: &Latch) {
+        self.keyboard.restore_latch(latch);
+    }
+
+    fn release_keys(&self) {
+        self.keyboard.release_keys();
     }
 
     fn update_keys(&self, block: bool) {
+        // ncurses picks out the function keys it knows about. Everything
+        // else (including kitty protocol sequences) comes through byte by
+        // byte, to be parsed like RawInput does.
+        let mut bytes = Vec::new();
 
         nc::timeout(if block { -1 } else { 0 });
-        let input = nc::getch();
-
-        // Match special values
-        match input {
-            // Exit Key
-            nc::KEY_F1 => {
-                self.exit.set(true);
-                return;
+        loop {
+            match nc::getch() {
+                // No (more) input
+                -1 => break,
+                k if k >= nc::KEY_F(1) && k <= nc::KEY_F(12) => {
+                    let n = (k - nc::KEY_F0) as u8;
+                    self.keyboard.handle(TermKey::Function(n,
+                                                           KeyEvent::Press));
+                }
+                k @ 0..=0xFF => bytes.push(k as u8),
+                _ => (),
             }
-            // No input
-            -1 => return,
-            // Otherwise, push forwards
-            _ => (),
-        }
-
-        // Keymap
-
-        let key_pressed = match char::from_u32(input as u32) {
-            #[cfg_attr(rustfmt, rustfmt_skip)] // keep the 4x4
-            Some(c) => match c {
-                '1' => 0x1, '2' => 0x2, '3' => 0x3, '4' => 0xC,
-                'q' => 0x4, 'w' => 0x5, 'e' => 0x6, 'r' => 0xD,
-                'a' => 0x7, 's' => 0x8, 'd' => 0x9, 'f' => 0xE,
-                'z' => 0xA, 'x' => 0x0, 'c' => 0xB, 'v' => 0xF,
-                _ => return,
-            },
-            None => return,
-        };
+            nc::timeout(0);
+        }
+
+        self.keyboard.feed(&bytes);
+    }
+}
+
+/* ----------  Raw Terminal Input  ---------- */
+// Reads keys straight from the terminal, without ncurses. The renderer is
+// expected to have put the terminal into raw mode (see AnsiDisplay).
+//
+// Keys arrive as plain characters, and function keys as escape sequences,
+// which differ between terminals (hence the several flavors of each).
+
+pub struct RawInput {
+    keyboard: Keyboard,
+}
+
+impl RawInput {
+    pub fn new() -> RawInput {
+        RawInput { keyboard: Keyboard::new() }
+    }
+}
 
-        // Recall that this key was just pressed
-        self.last_press.set(Some(key_pressed));
+impl Get for RawInput {
+    fn pressed_esc(&self) -> bool {
+        self.keyboard.exit.get()
+    }
+    fn hotkey(&self) -> Option<Hotkey> {
+        self.keyboard.hotkey.take()
+    }
+
+    fn pressed_key(&self, key: u8) -> bool {
+        self.keyboard.pressed_key(key)
+    }
+
+    fn last_press(&self) -> Option<u8> {
+        self.keyboard.last_press.take()
+    }
+
+    fn latch(&self) -> Latch {
+        self.keyboard.latch()
+    }
+}
+
+impl Set for RawInput {
+    fn decrement_keys(&self) {
+        self.keyboard.decrement_keys();
+    }
+
+    fn restore_latch(&self, latch: &Latch) {
+        self.keyboard.restore_latch(latch);
+    }
+
+    fn release_keys(&self) {
+        self.keyboard.release_keys();
+    }
+
+    fn update_keys(&self, block: bool) {
+        let mut buf = [0; 256];
+        let n = tty::read_input(&mut buf, block);
+
+        self.keyboard.feed(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use super::KeyEvent::*;
+    use super::TermKey::*;
+
+    fn held(input: &dyn Input) -> Vec<u8> {
+        (0..16).filter(|key| input.pressed_key(*key)).collect()
+    }
+
+    #[test]
+    fn movie_plays_back() {
+        let script = "2 5\n4 5 A\n7 -\n9 F\n";
+        let path = env::temp_dir()
+            .join(format!("ac8e-test-{}.movie", process::id()));
+        let path = path.to_str().unwrap();
+
+        // record a scripted run, then play the movie back alongside it
+        let inner = Box::new(ScriptedInput::parse(script).unwrap());
+        let recording = RecordingInput::create(inner, path, 42).unwrap();
+        let mut frames = Vec::new();
+        for _ in 0..12 {
+            frames.push(held(&recording));
+            recording.decrement_keys();
+        }
+        drop(recording);
+
+        let movie = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).ok();
+        assert_eq!(movie_seed(&movie), Some(42));
+
+        let playback = ScriptedInput::parse(&movie).unwrap();
+        for keys in &frames {
+            assert_eq!(&held(&playback), keys);
+            playback.decrement_keys();
+        }
+        assert_eq!(frames[4], vec![0x5, 0xA]);
+    }
+
+    fn keys(bytes: &[u8]) -> Vec<TermKey> {
+        let (keys, used) = parse_term_keys(bytes);
+        assert_eq!(used, bytes.len());
+        keys
+    }
+
+    #[test]
+    fn plain_keys() {
+        assert_eq!(keys(b"q\x03\x1bw"),
+                   vec![Char('q', Press), Interrupt, Char('w', Press)]);
+    }
+
+    #[test]
+    fn xterm_function_keys() {
+        assert_eq!(keys(b"\x1bOP\x1bOS"),
+                   vec![Function(1, Press), Function(4, Press)]);
+        // with modifiers
+        assert_eq!(keys(b"\x1b[1;2Q"), vec![Function(2, Press)]);
+        // Linux console
+        assert_eq!(keys(b"\x1b[[E"), vec![Function(5, Press)]);
+    }
+
+    #[test]
+    fn vt220_function_keys() {
+        assert_eq!(keys(b"\x1b[11~\x1b[15~\x1b[17~\x1b[24~"),
+                   vec![Function(1, Press),
+                        Function(5, Press),
+                        Function(6, Press),
+                        Function(12, Press)]);
+        // 16 and 22 aren't keys, and unknown sequences are skipped whole
+        assert_eq!(keys(b"\x1b[16~\x1b[2;5Hx"),
+                   vec![Char('x', Press)]);
+    }
+
+    #[test]
+    fn kitty_keys() {
+        assert_eq!(keys(b"\x1b[119u\x1b[119;1:2u\x1b[119;1:3u"),
+                   vec![Char('w', Press),
+                        Char('w', Repeat),
+                        Char('w', Release)]);
+        assert_eq!(keys(b"\x1b[99;5u\x1b[99;5:3u"),
+                   vec![Interrupt]);
+        assert_eq!(keys(b"\x1b[15;1:3~"),
+                   vec![Function(5, Release)]);
+        assert_eq!(parse_csi(b"?11", b'u'), Some(KittyFlags));
+        assert_eq!(parse_csi(b"?3", b'u'), Some(KittyFlags));
+        assert_eq!(parse_csi(b"?11", b'c'), None);
+        // speaks the protocol, but won't send releases
+        assert_eq!(parse_csi(b"?1", b'u'), None);
+        assert_eq!(parse_csi(b"?0", b'u'), None);
+    }
+
+    #[test]
+    fn restore_keeps_held_keys() {
+        let keyboard = Keyboard::new();
+        keyboard.handle(KittyFlags);
+        keyboard.handle(Char('w', Press));
+
+        // still held after a rewind to before it was pressed...
+        keyboard.restore_latch(&Latch::default());
+        assert!(keyboard.pressed_key(0x5));
+
+        // ...until it's released, or might have been
+        keyboard.release_keys();
+        assert!(!keyboard.pressed_key(0x5));
+    }
+    #[test]
+    fn split_sequences() {
+        // what's cut off is left over
+        assert_eq!(parse_term_keys(b"w\x1b[119;1:"),
+                   (vec![Char('w', Press)], 1));
+        assert_eq!(parse_term_keys(b"\x1bO"), (vec![], 0));
+        assert_eq!(parse_term_keys(b"\x1b"), (vec![], 0));
 
-        // This number controls "key stickieness"
-        self.keys.borrow_mut()[key_pressed as usize] = 8;
+        // and picked up again with the rest of it
+        let keyboard = Keyboard::new();
+        keyboard.feed(b"w\x1b[119;1");
+        assert!(keyboard.pressed_key(0x5));
+        keyboard.feed(b":3u\x1bO");
+        assert!(!keyboard.pressed_key(0x5));
+        keyboard.feed(b"Q"); // F2
+        assert_eq!(keyboard.hotkey.get(), Some(Hotkey::Pause));
     }
 }
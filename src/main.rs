@@ -1,30 +1,126 @@
+extern crate rand;
+
 use std::env;
 use std::fs::File;
 use std::io::Read;
 
+mod audio;
+mod cdp1802;
+mod cli;
+mod clock;
+mod conformance;
 mod cpu;
+mod crash;
+mod debugger;
 mod disasm;
 mod display;
+mod error;
+mod frontend;
+mod graphics;
 mod input;
+mod quirks;
 mod ram;
+mod rng;
+mod regress;
+mod rewind;
+mod savestate;
+mod screenshot;
+mod sound;
+mod stack;
+mod timing;
+mod tty;
 mod types;
+mod vip;
 
-use display::Render;
-use input::Get;
-use input::Set;
+// Show the current frame, and make some noise if need be
+fn present(cpu: &cpu::CPU) {
+    let frontend = cpu.frontend();
+    frontend.display.render(cpu.screen());
+    frontend.sound.play(cpu.is_beeping(), cpu.audio());
+}
 
 fn main() {
-    // read ROM path from cli
-    let path = match env::args().nth(1) {
-        Some(path) => path,
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let opts = match cli::parse(&args) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            println!("{}", cli::usage());
+            return;
+        }
+        Err(why) => {
+            println!("{}\n\n{}", why, cli::usage());
+            std::process::exit(1);
+        }
+    };
+
+    // run the test ROMs, instead of a game
+    if opts.conformance {
+        let passed = conformance::run_all(&opts.rom);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    // check the games still behave like they used to
+    if opts.regress {
+        let passed = regress::run_all(&opts.rom, opts.bless);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    // pick how to display the screen / get input / play sound
+    let frontend = frontend::Frontend::from_name(&opts.frontend,
+                                                 opts.palette,
+                                                 opts.scale);
+    let mut frontend = match frontend {
+        Some(frontend) => frontend,
         None => {
-            println!("Usage: ac8e <romfile>");
+            println!("unknown frontend, expected one of: {}",
+                     frontend::FRONTENDS.join(", "));
             std::process::exit(1);
         }
     };
 
+    let mut seed = opts.seed.unwrap_or_else(rand::random);
+
+    // replay scripted key presses (or a movie), instead of reading them from
+    // the user
+    if let Some(ref path) = opts.input_script {
+        let mut script = String::new();
+        if File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut script))
+            .is_err() {
+            println!("couldn't read '{}'", path);
+            std::process::exit(1);
+        }
+
+        // movies remember the seed they were recorded with
+        if opts.seed.is_none() {
+            seed = input::movie_seed(&script).unwrap_or(seed);
+        }
+
+        match input::ScriptedInput::parse(&script) {
+            Ok(scripted) => frontend.input = Box::new(scripted),
+            Err(why) => {
+                println!("{}", why);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // record key presses into a movie
+    if let Some(ref path) = opts.record {
+        let inner = frontend.input;
+        let recording = input::RecordingInput::create(inner, path, seed);
+        frontend.input = match recording {
+            Ok(recording) => Box::new(recording),
+            Err(why) => {
+                println!("{}", why);
+                std::process::exit(1);
+            }
+        };
+    }
+
     // try to open ROM file
-    let f = match File::open(&path) {
+    let path = &opts.rom;
+    let mut f = match File::open(path) {
         Ok(file) => file,
         Err(_) => {
             println!("couldn't find '{}'", path);
@@ -32,54 +128,176 @@ fn main() {
         }
     };
 
+    // read the whole ROM up-front
+    let mut rom = Vec::new();
+    if f.read_to_end(&mut rom).is_err() {
+        println!("couldn't read '{}'", path);
+        std::process::exit(1);
+    }
+
+    // the VIP runs the ROM on its own interpreter
+    if opts.vip_interpreter.is_some() {
+        if let Err(why) = vip::run(&opts, frontend, &rom) {
+            println!("{}", why);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
     // --- init RAM
-    let mut ram = ram::RAM::new();
+    // XO-CHIP programs need the full 64 KiB of RAM
+    let mut ram = if opts.xo_chip {
+        ram::RAM::with_size(0x10000)
+    } else {
+        ram::RAM::new()
+    };
 
     // Load the rom file into RAM (before handing RAM to CPU)
-    for (i, byte) in f.bytes().enumerate() {
-        // make sure the byte read correctly
-        let byte = match byte {
-            Ok(byte) => byte,
-            Err(_) => {
-                println!("couldn't read '{}'", path);
-                std::process::exit(1);
-            }
-        };
-
-        // And make sure it is loaded into RAM properly too
-        if let Err(why) = ram.store_u8(0x200 + i as u16, byte) {
+    if opts.load_addr as usize + rom.len() > ram.size() {
+        println!("ROM too large: {} bytes don't fit in RAM at 0x{:03x}",
+                 rom.len(),
+                 opts.load_addr);
+        std::process::exit(1);
+    }
+    for (i, byte) in rom.into_iter().enumerate() {
+        // make sure it is loaded into RAM properly
+        if let Err(why) = ram.store_u8(opts.load_addr + i as u16, byte) {
             println!("{}", why);
             std::process::exit(1);
         }
     }
 
-    // --- init display
-    let display = display::NcursesDisplay::new();
-    display.init();
-
-    // --- init input
-    let input = input::NcursesInput::new();
+    // --- init frontend
+    frontend.display.init();
 
     // --- init CPU,
-    // the CPU takes a handle to
+    // the CPU takes ownership of
     //   - RAM
-    //   - Display
-    //   - Input
-    let mut cpu = cpu::CPU::new(&mut ram, &display, &input);
+    //   - the Frontend (Display, Input, and Sound)
+    let mut cpu =
+        cpu::CPU::new(ram, frontend, opts.quirks, opts.load_addr, seed);
+
+    // resume from a save state
+    if let Some(ref path) = opts.load_state {
+        if let Err(why) = savestate::load_file(&mut cpu, path) {
+            cpu.frontend().display.uninit();
+            println!("{}", why);
+            std::process::exit(1);
+        }
+    }
+
+    let mut clock = clock::Clock::new(opts.timer_hz);
+    let mut budget = timing::Budget::new(if opts.vip_timing {
+        timing::Pacing::Vip
+    } else {
+        timing::Pacing::Fixed(opts.ipf)
+    });
+    let mut paused = opts.paused;
+    let mut frames = 0;
+    let mut status = 0; // exit code
+
+    // save / load state failures, shown on exit (the screen is busy until
+    // then)
+    let mut state_errors = Vec::new();
+
+    // keep a few seconds of history around to rewind through
+    // (not much point when there's nobody around to rewind)
+    let rewind_frames = if opts.headless {
+        0
+    } else {
+        opts.rewind_secs * opts.timer_hz
+    };
+    let mut history = rewind::History::new(rewind_frames as usize);
+    history.record(&cpu);
+
+    let mut debugger = debugger::Debugger::new();
+    if opts.debug {
+        debugger.interrupt();
+    }
+
+    // the last few instructions, for the crash report
+    let mut trace = crash::Trace::new();
 
     // Loop!
     'mainLoop: loop {
-        // Each loop is ~ 1/120th of a second
-        std::thread::sleep(std::time::Duration::from_millis(8));
+        // Each loop is one frame (1/60th of a second by default), kept in
+        // time with the wall clock. Headless runs go as fast as they can.
+        let on_time = opts.headless || clock.wait();
+
+        match cpu.frontend().input.hotkey() {
+            Some(input::Hotkey::Pause) => paused = !paused,
+            Some(input::Hotkey::Debug) => debugger.interrupt(),
+            Some(input::Hotkey::Rewind) => {
+                // the key repeats while held, so rewind one frame at a time
+                if history.rewind(&mut cpu, 1).is_ok() {
+                    trace.clear();
+                }
+                present(&cpu);
+                cpu.frontend().input.update_keys(false);
+                continue;
+            }
+            Some(input::Hotkey::SaveState(slot)) => {
+                let path = savestate::slot_path(&opts.rom, slot);
+                // not fatal, just keep on running
+                if let Err(why) = savestate::save_file(&cpu, &path) {
+                    state_errors.push(why);
+                }
+            }
+            // the movie couldn't be played back from power-on anymore
+            Some(input::Hotkey::LoadState(_)) if opts.record.is_some() => {
+                let why = "[State] Can't load a state while recording a movie";
+                state_errors.push(why.to_string());
+            }
+            Some(input::Hotkey::LoadState(slot)) => {
+                let path = savestate::slot_path(&opts.rom, slot);
+                match savestate::load_file(&mut cpu, &path) {
+                    Ok(()) => trace.clear(),
+                    Err(why) => state_errors.push(why),
+                }
+            }
+            None => (),
+        }
+
+        if paused {
+            // keep showing the screen, and wait for the user to resume
+            present(&cpu);
+            cpu.frontend().input.update_keys(true); // blocking
+            if cpu.frontend().input.pressed_esc() {
+                break 'mainLoop;
+            }
+            continue;
+        }
 
         // Run the CPU faster than the screen refreshes
-        for _ in 0..5 {
+        budget.start_frame();
+        while budget.next(&cpu) {
+            // Hand control over to the debugger if need be
+            if debugger.should_break(&mut cpu) {
+                cpu.frontend().display.suspend();
+                let action = debugger.repl(&mut cpu, &mut history, &mut trace);
+                cpu.frontend().display.resume();
+                // key releases aren't reported while suspended
+                cpu.frontend().input.release_keys();
+
+                if let debugger::Action::Quit = action {
+                    break 'mainLoop;
+                }
+            }
+
             // Run the cpu, and get it's state
+            trace.record(&cpu);
             let cpu_state = match cpu.cycle() {
                 // Shutdown everything if shit hits the fan
                 Err(why) => {
-                    display.uninit();
-                    print!("\n{}\n", why);
+                    cpu.frontend().display.uninit();
+                    let report = crash::report(&cpu, &trace, &why);
+                    print!("\n{}", report);
+                    if let Some(ref path) = opts.crash_report {
+                        if let Err(why) = crash::write(&report, path) {
+                            println!("{}", why);
+                        }
+                    }
+                    status = 1;
                     break 'mainLoop;
                 }
                 Ok(state) => state,
@@ -88,16 +306,18 @@ fn main() {
             match cpu_state {
                 cpu::CPUState::WaitForInput => {
                     // render the screen before blocking
-                    display.render(cpu.is_beeping());
-                    input.update_keys(true); // blocking operation
+                    present(&cpu);
+                    cpu.frontend().input.update_keys(true); // blocking
                 }
                 cpu::CPUState::Running => {
-                    input.update_keys(false);
+                    cpu.frontend().input.update_keys(false);
                 }
+                // The ROM asked to quit (SCHIP 00FD)
+                cpu::CPUState::Exit => break 'mainLoop,
             };
 
             // check if user wants to exit
-            if input.pressed_esc() {
+            if cpu.frontend().input.pressed_esc() {
                 break 'mainLoop;
             }
         }
@@ -106,11 +326,35 @@ fn main() {
         cpu.decrement_counters();
 
         // ...
-        input.decrement_keys();
+        cpu.frontend().input.decrement_keys();
+
+        // Render the screen, unless we need to catch up
+        if on_time {
+            present(&cpu);
+        }
+
+        // Remember this frame, in case the user wants to come back to it
+        history.record(&cpu);
+
+        // Stop if we've run for as long as we were asked to
+        frames += 1;
+        if opts.frames.is_some_and(|limit| frames >= limit) {
+            break 'mainLoop;
+        }
+    }
 
-        // Render the screen
-        display.render(cpu.is_beeping());
+    cpu.frontend().display.uninit();
+
+    for why in &state_errors {
+        println!("{}", why);
+    }
+
+    if let Some(ref path) = opts.dump {
+        if let Err(why) = screenshot::write(cpu.screen(), path) {
+            println!("{}", why);
+            status = 1;
+        }
     }
 
-    display.uninit();
+    std::process::exit(status);
 }
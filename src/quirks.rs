@@ -0,0 +1,139 @@
+// Different CHIP-8 interpreters disagree on how a handful of instructions
+// behave. Most ROMs were written against a specific interpreter, and will
+// misbehave if run with the "wrong" interpretation.
+//
+// A `Quirks` struct describes one set of behaviors, and is consulted by the
+// CPU whenever it executes one of these contested instructions.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LoadStore {
+    IncrementByXPlusOne, // I = I + x + 1 (COSMAC VIP)
+    IncrementByX,        // I = I + x (CHIP-48 / SCHIP 1.0)
+    Unchanged,           // I is left alone (SCHIP 1.1)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks {
+    // 8xy6 / 8xyE shift Vy and store the result in Vx, instead of shifting
+    // Vx in place
+    pub shift_vy: bool,
+    // How Fx55 / Fx65 affect I
+    pub load_store: LoadStore,
+    // Bnnn is actually Bxnn, and jumps to xnn + Vx instead of nnn + V0
+    pub jump_vx: bool,
+    // 8xy1 / 8xy2 / 8xy3 reset VF to 0
+    pub vf_reset: bool,
+    // Sprites are clipped at the edges of the screen instead of wrapping
+    // around to the other side
+    pub clip_sprites: bool,
+    // Dxy0 draws a 16x16 sprite in lores mode too, not just in hires mode
+    pub lores_wide_sprites: bool,
+    // Fx1E sets VF when I overflows past 0xFFF (Amiga interpreter, relied
+    // upon by "Spacefight 2019!" and checked by SCTEST)
+    pub i_overflow_vf: bool,
+    // How many return addresses 2nnn can push before the stack overflows
+    pub stack_depth: usize,
+    // Overflowing the stack loses the oldest return address, instead of
+    // halting with an error
+    pub stack_wrap: bool,
+    // The stack lives in RAM, growing down from 0xECF (COSMAC VIP), where
+    // ROMs can read / modify it
+    pub stack_in_ram: bool,
+}
+
+impl Default for Quirks {
+    // The behavior AC8E has always had
+    fn default() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            lores_wide_sprites: false,
+            i_overflow_vf: false,
+            stack_depth: 16,
+            stack_wrap: false,
+            stack_in_ram: false,
+        }
+    }
+}
+
+impl Quirks {
+    // The original 1977 interpreter
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_vy: true,
+            load_store: LoadStore::IncrementByXPlusOne,
+            jump_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            stack_depth: 12,
+            stack_in_ram: true,
+            ..Quirks::default()
+        }
+    }
+
+    // CHIP-48, for the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::IncrementByX,
+            jump_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            ..Quirks::default()
+        }
+    }
+
+    // SUPER-CHIP 1.0 behaves just like CHIP-48
+    pub fn schip10() -> Quirks {
+        Quirks::chip48()
+    }
+
+    // SUPER-CHIP 1.1 stopped incrementing I on Fx55 / Fx65
+    pub fn schip11() -> Quirks {
+        Quirks {
+            load_store: LoadStore::Unchanged,
+            ..Quirks::chip48()
+        }
+    }
+
+    // XO-CHIP (as implemented by Octo)
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_vy: true,
+            load_store: LoadStore::IncrementByXPlusOne,
+            jump_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            lores_wide_sprites: true,
+            ..Quirks::default()
+        }
+    }
+
+    // The Amiga interpreter, which sets VF when Fx1E overflows I
+    pub fn amiga() -> Quirks {
+        Quirks {
+            i_overflow_vf: true,
+            ..Quirks::default()
+        }
+    }
+
+    // Look up a preset by name
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "ac8e" => Some(Quirks::default()),
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip10" => Some(Quirks::schip10()),
+            "schip11" => Some(Quirks::schip11()),
+            "xochip" => Some(Quirks::xo_chip()),
+            "amiga" => Some(Quirks::amiga()),
+            _ => None,
+        }
+    }
+}
+
+pub const PRESETS: [&str; 7] =
+    ["ac8e", "vip", "chip48", "schip10", "schip11", "xochip", "amiga"];
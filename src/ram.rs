@@ -1,20 +1,83 @@
 #![allow(dead_code)]
 
+use error::EmuError;
+use savestate;
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct RAM {
-    mem: [u8; 0x1000],
+    mem: Vec<u8>,
+
+    // Debugger support: writes to any of these addresses are recorded
+    watchpoints: Vec<u16>,
+    watch_hit: Option<u16>,
 }
 
-fn err_oob(addr: u16) -> String {
-    format!("[RAM] Address 0x{:03x} is out of bounds!", addr)
+// The CPU fills in which instruction made the access
+fn err_oob(addr: u16) -> EmuError {
+    EmuError::OutOfBounds {
+        pc: None,
+        opcode: None,
+        addr,
+    }
 }
 
 impl RAM {
+    // Regular 4 KiB of CHIP-8 memory
     pub fn new() -> RAM {
-        RAM { mem: [0; 0x1000] }
+        RAM::with_size(0x1000)
+    }
+
+    // XO-CHIP programs get the full 64 KiB address space
+    pub fn with_size(size: usize) -> RAM {
+        RAM {
+            mem: vec![0; size],
+
+            watchpoints: vec![],
+            watch_hit: None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn set_watchpoints(&mut self, addrs: &[u16]) {
+        self.watchpoints = addrs.to_vec();
+    }
+
+    // Returns the watched address that was written to since the last call
+    pub fn take_watch_hit(&mut self) -> Option<u16> {
+        self.watch_hit.take()
     }
 
-    pub fn load_u16(&self, addr: u16) -> Result<u16, String> {
-        if addr >= 0xFFF {
+    pub fn save_state(&self, w: &mut savestate::Writer) {
+        w.blob(&self.mem);
+    }
+
+    // Only reads the memory back, for CPU::load_state to restore() once the
+    // rest of the state has been read too
+    pub fn load_state(&self, r: &mut savestate::Reader)
+                      -> Result<Vec<u8>, String> {
+        let mem = r.blob()?;
+        if mem.len() != self.mem.len() {
+            return Err(format!("[State] RAM size mismatch: {} bytes, \
+                                expected {}", mem.len(), self.mem.len()));
+        }
+        Ok(mem.to_vec())
+    }
+
+    pub fn restore(&mut self, mem: Vec<u8>) {
+        self.mem = mem;
+    }
+
+    fn check_watch(&mut self, addr: u16) {
+        if self.watchpoints.contains(&addr) {
+            self.watch_hit = Some(addr);
+        }
+    }
+
+    pub fn load_u16(&self, addr: u16) -> Result<u16, EmuError> {
+        if addr as usize + 1 >= self.mem.len() {
             return Err(err_oob(addr));
         }
 
@@ -24,31 +87,38 @@ impl RAM {
         Ok(word)
     }
 
-    pub fn store_u16(&mut self, addr: u16, val: u16) -> Result<(), String> {
-        if addr >= 0xFFF {
+    pub fn store_u16(&mut self, addr: u16, val: u16)
+                     -> Result<(), EmuError> {
+        if addr as usize + 1 >= self.mem.len() {
             return Err(err_oob(addr));
         }
 
+        self.check_watch(addr);
+        self.check_watch(addr + 1);
+
         let i = addr as usize;
-        self.mem[i + 0] = (val >> 8) as u8;
-        self.mem[i + 1] = (val >> 0) as u8;
+        self.mem[i] = (val >> 8) as u8;
+        self.mem[i + 1] = val as u8;
 
         Ok(())
     }
 
-    pub fn load_u8(&self, addr: u16) -> Result<u8, String> {
-        if addr >= 0xFFF {
+    pub fn load_u8(&self, addr: u16) -> Result<u8, EmuError> {
+        if addr as usize >= self.mem.len() {
             return Err(err_oob(addr));
         }
 
         Ok(self.mem[addr as usize])
     }
 
-    pub fn store_u8(&mut self, addr: u16, val: u8) -> Result<(), String> {
-        if addr >= 0xFFF {
+    pub fn store_u8(&mut self, addr: u16, val: u8)
+                    -> Result<(), EmuError> {
+        if addr as usize >= self.mem.len() {
             return Err(err_oob(addr));
         }
 
+        self.check_watch(addr);
+
         self.mem[addr as usize] = val;
         Ok(())
     }
@@ -0,0 +1,227 @@
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+
+use conformance;
+use display::ScreenRAM;
+use input::ScriptedInput;
+use quirks::Quirks;
+
+// Golden-frame regression suite.
+//
+// Runs every game in <romdir>/games for a fixed number of frames, with a
+// fixed RNG seed and scripted key presses, and compares the final screen with
+// the one stored in <romdir>/golden/<game>.txt. Blessing rewrites the golden
+// files with whatever is on screen now.
+//
+// Golden files are a header with a hash of the screen, followed by the
+// screen itself, one character per pixel ('.' for unlit pixels):
+//
+//   # BRIX: 600 frames, seed 1
+//   # hash 9f3a6c0e5b1d2a47
+//   ....XXXX....
+//
+// Games that need specific input can provide <romdir>/golden/<game>.input,
+// in the same format as `--input` scripts.
+
+const FRAMES: u32 = 600;
+const SEED: u64 = 1;
+
+// Mash a few common keys, so games that wait for input get going
+const DEFAULT_INPUT: &str = "\
+30  5
+40  -
+60  4
+90  -
+120 6
+150 -
+180 1 C
+210 -
+300 7 9
+330 -
+";
+
+// Characters for each pixel value in golden files (not '#', which starts the
+// header lines)
+const PIXELS: [char; 4] = ['.', 'X', 'O', '@'];
+
+// FNV-1a, over the resolution and every pixel
+fn hash(screen: &ScreenRAM) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut feed = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    };
+
+    feed(screen.width() as u8);
+    feed(screen.height() as u8);
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            feed(screen.pixel(x, y));
+        }
+    }
+
+    hash
+}
+
+fn rows(screen: &ScreenRAM) -> Vec<String> {
+    (0..screen.height())
+        .map(|y| {
+            (0..screen.width())
+                .map(|x| PIXELS[screen.pixel(x, y) as usize])
+                .collect()
+        })
+        .collect()
+}
+
+fn read_file(path: &str) -> Option<String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .ok()
+        .map(|_| contents)
+}
+
+// Marks every pixel that changed: '+' if it is now lit, '-' if it isn't
+fn diff(golden: &[&str], actual: &[String]) -> String {
+    if golden.len() != actual.len() ||
+       golden.iter().zip(actual).any(|(g, a)| g.len() != a.len()) {
+        return format!("    resolution changed\n\
+                        \x20   expected:\n{}\n\
+                        \x20   got:\n{}\n",
+                       golden.join("\n"),
+                       actual.join("\n"));
+    }
+
+    let mut changed = 0;
+    let mut out = String::new();
+    for (g, a) in golden.iter().zip(actual) {
+        for (g, a) in g.chars().zip(a.chars()) {
+            out.push(match (g, a) {
+                (g, a) if g == a => a,
+                (_, '.') => '-',
+                _ => '+',
+            });
+            changed += (g != a) as usize;
+        }
+        out.push('\n');
+    }
+
+    format!("    {} pixel(s) changed ('+' now lit, '-' now unlit):\n{}",
+            changed,
+            out)
+}
+
+// Runs every game in `dir`/games. Returns true if none of them changed.
+pub fn run_all(dir: &str, bless: bool) -> bool {
+    let mut games = match fs::read_dir(format!("{}/games", dir)) {
+        Ok(entries) => {
+            entries.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<String>>()
+        }
+        Err(why) => {
+            println!("couldn't read '{}/games': {}", dir, why);
+            return false;
+        }
+    };
+    games.sort();
+
+    let golden_dir = format!("{}/golden", dir);
+    if bless {
+        fs::create_dir_all(&golden_dir).ok();
+    }
+
+    let mut passed = true;
+    for game in &games {
+        let golden_path = format!("{}/{}.txt", golden_dir, game);
+        let input_path = format!("{}/{}.input", golden_dir, game);
+
+        let script = read_file(&input_path)
+            .unwrap_or_else(|| DEFAULT_INPUT.to_string());
+        let run = ScriptedInput::parse(&script).and_then(|input| {
+            conformance::run_rom(&format!("{}/games/{}", dir, game),
+                                 Quirks::default(),
+                                 Box::new(input),
+                                 SEED,
+                                 FRAMES)
+        });
+
+        let cpu = match run {
+            Ok(cpu) => cpu,
+            Err(why) => {
+                println!("{:<10} FAIL ({})", game, why);
+                passed = false;
+                continue;
+            }
+        };
+
+        let hash = format!("{:016x}", hash(cpu.screen()));
+        let actual = rows(cpu.screen());
+
+        if bless {
+            let contents = format!("# {}: {} frames, seed {}\n# hash {}\n{}\n",
+                                   game,
+                                   FRAMES,
+                                   SEED,
+                                   hash,
+                                   actual.join("\n"));
+            match File::create(&golden_path)
+                .and_then(|mut f| f.write_all(contents.as_bytes())) {
+                Ok(()) => println!("{:<10} blessed", game),
+                Err(why) => {
+                    println!("{:<10} FAIL (couldn't write '{}': {})",
+                             game,
+                             golden_path,
+                             why);
+                    passed = false;
+                }
+            }
+            continue;
+        }
+
+        let golden = match read_file(&golden_path) {
+            Some(golden) => golden,
+            None => {
+                println!("{:<10} FAIL (no golden frame, run with --bless)",
+                         game);
+                passed = false;
+                continue;
+            }
+        };
+
+        let golden_hash = golden.lines()
+            .filter_map(|line| line.strip_prefix("# hash "))
+            .next()
+            .unwrap_or("");
+        if golden_hash == hash {
+            println!("{:<10} pass", game);
+            continue;
+        }
+
+        let golden_rows = golden.lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<&str>>();
+        println!("{:<10} FAIL (hash {}, expected {})",
+                 game,
+                 hash,
+                 golden_hash);
+        print!("{}", diff(&golden_rows, &actual));
+        passed = false;
+    }
+
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_all;
+
+    // (cargo runs tests from the crate root, where roms/ lives)
+    #[test]
+    fn golden_frames() {
+        assert!(run_all("roms", false), "golden frames changed, see above");
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+
+use cpu::CPU;
+use savestate;
+
+// Rewind history.
+//
+// Keeps the most recent save state in full, and a bounded ring of deltas
+// that each turn a snapshot back into the one taken before it. Consecutive
+// frames tend to be nearly identical, so each delta is the XOR of the two
+// snapshots, run-length encoded: runs of unchanged (zero) bytes cost 4 bytes
+// total, no matter how long they are.
+//
+// Delta format, repeated until the end of the buffer:
+//
+//   u16 length of a run of unchanged bytes
+//   u16 length of a run of changed bytes, followed by the XORed bytes
+//
+// prefixed by the u32 length of the older snapshot (since the screen buffer
+// changes size when switching resolutions).
+
+pub struct History {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl History {
+    // Keep (up to) `capacity` snapshots before the latest one
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Snapshot the machine
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let snapshot = savestate::save(cpu);
+        if let Some(latest) = self.latest.take() {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode(&snapshot, &latest));
+        }
+        self.latest = Some(snapshot);
+    }
+
+    // Go back `n` snapshots. Returns the number of snapshots actually
+    // rewound, which is less than `n` if the history runs out.
+    pub fn rewind(&mut self, cpu: &mut CPU, n: usize) -> Result<usize, String> {
+        let mut rewound = 0;
+        while rewound < n {
+            let delta = match self.deltas.pop_back() {
+                Some(delta) => delta,
+                None => break,
+            };
+            let latest = self.latest.take().unwrap_or_default();
+            self.latest = Some(decode(&latest, &delta));
+            rewound += 1;
+        }
+
+        if let Some(ref snapshot) = self.latest {
+            savestate::load(cpu, snapshot)?;
+            // (so a movie being recorded goes back too)
+            cpu.frontend().input.rewind(rewound as u64);
+        }
+        Ok(rewound)
+    }
+}
+
+/* ----------  Delta compression  ---------- */
+
+// Cap run lengths so they fit in a u16
+const MAX_RUN: usize = 0xFFFF;
+
+// Encodes the delta that turns `new` into `old`
+fn encode(new: &[u8], old: &[u8]) -> Vec<u8> {
+    let byte_at = |buf: &[u8], i: usize| buf.get(i).cloned().unwrap_or(0);
+    let xor = |i: usize| byte_at(new, i) ^ byte_at(old, i);
+    let len = new.len().max(old.len());
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&(old.len() as u32).to_be_bytes());
+
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        while i < len && i - start < MAX_RUN && xor(i) == 0 {
+            i += 1;
+        }
+        out.extend_from_slice(&((i - start) as u16).to_be_bytes());
+
+        let start = i;
+        while i < len && i - start < MAX_RUN && xor(i) != 0 {
+            i += 1;
+        }
+        out.extend_from_slice(&((i - start) as u16).to_be_bytes());
+        out.extend((start..i).map(&xor));
+    }
+
+    out
+}
+
+// Applies a delta from `encode` to `new`, giving back `old`
+fn decode(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u16 =
+        |at: usize| (delta[at] as usize) << 8 | delta[at + 1] as usize;
+
+    let old_len = delta[..4]
+        .iter()
+        .fold(0, |acc, byte| acc << 8 | *byte as usize);
+
+    let mut out = new.to_vec();
+    out.resize(old_len.max(new.len()), 0);
+
+    let mut pos = 4;
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_u16(pos);
+        let changed = read_u16(pos + 2);
+        pos += 4;
+
+        for byte in &delta[pos..pos + changed] {
+            out[i] ^= *byte;
+            i += 1;
+        }
+        pos += changed;
+    }
+
+    out.truncate(old_len);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::rc::Rc;
+
+    use super::{decode, encode, History, MAX_RUN};
+    use cpu::CPU;
+    use frontend::Frontend;
+    use input::{Get, Hotkey, Input, Latch, RecordingInput, ScriptedInput, Set};
+    use quirks::Quirks;
+    use ram::RAM;
+    use savestate;
+
+    fn round_trip(new: &[u8], old: &[u8]) {
+        assert_eq!(decode(new, &encode(new, old)), old);
+    }
+
+    #[test]
+    fn same_size() {
+        let old = (0..200).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut new = old.clone();
+        new[0] ^= 0xFF;
+        new[50] = 7;
+        new[199] ^= 1;
+        round_trip(&new, &old);
+        round_trip(&old, &old);
+    }
+
+    #[test]
+    fn resized() {
+        let small = vec![1; 100];
+        let big = (0..300).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+        round_trip(&small, &big);
+        round_trip(&big, &small);
+        round_trip(&[], &big);
+        round_trip(&big, &[]);
+    }
+
+    #[test]
+    fn long_runs() {
+        // runs longer than fit in a u16, both unchanged and changed
+        let old = vec![0; MAX_RUN * 3];
+        let mut new = old.clone();
+        for byte in &mut new[MAX_RUN / 2..MAX_RUN * 2 + 10] {
+            *byte = 0xAA;
+        }
+        round_trip(&new, &old);
+        round_trip(&old, &new);
+    }
+
+    #[test]
+    fn unchanged_is_small() {
+        let old = vec![0x5A; 10000];
+        // the old length, then a single run
+        assert_eq!(encode(&old, &old).len(), 4 + 4);
+    }
+
+    /* ----------  Recording  ---------- */
+
+    // Holds key 5 whenever the test says so
+    struct Finger(Rc<Cell<bool>>);
+
+    impl Get for Finger {
+        fn pressed_esc(&self) -> bool {
+            false
+        }
+        fn hotkey(&self) -> Option<Hotkey> {
+            None
+        }
+
+        fn pressed_key(&self, key: u8) -> bool {
+            key == 5 && self.0.get()
+        }
+        fn last_press(&self) -> Option<u8> {
+            None
+        }
+
+        fn latch(&self) -> Latch {
+            Latch::default()
+        }
+    }
+
+    impl Set for Finger {
+        fn decrement_keys(&self) {}
+        fn update_keys(&self, _block: bool) {}
+
+        fn restore_latch(&self, _latch: &Latch) {}
+    }
+
+    // Counts the instructions run with key 5 held (in V1), and rolls dice
+    fn counter(input: Box<dyn Input>) -> CPU {
+        let mut ram = RAM::new();
+        let program = [0x6005, 0xE0A1, 0x7101, 0xC2FF, 0x1202];
+        for (n, instr) in program.iter().enumerate() {
+            ram.store_u16(0x200 + 2 * n as u16, *instr).unwrap();
+        }
+
+        let mut frontend = Frontend::null();
+        frontend.input = input;
+        CPU::new(ram, frontend, Quirks::default(), 0x200, 7)
+    }
+
+    fn run_frame(cpu: &mut CPU, history: &mut History) {
+        for _ in 0..4 {
+            cpu.cycle().unwrap();
+        }
+        cpu.decrement_counters();
+        cpu.frontend().input.decrement_keys();
+        history.record(cpu);
+    }
+
+    #[test]
+    fn rewind_while_recording() {
+        let path = env::temp_dir()
+            .join(format!("ac8e-rewind-{}.movie", process::id()));
+        let path = path.to_str().unwrap();
+
+        // hold 5 for a bit, then rewind and hold it for longer instead
+        let held = Rc::new(Cell::new(false));
+        let finger = Box::new(Finger(held.clone()));
+        let recording = RecordingInput::create(finger, path, 7).unwrap();
+        let mut cpu = counter(Box::new(recording));
+        let mut history = History::new(100);
+        history.record(&cpu);
+
+        for frame in 0..10 {
+            held.set((3..6).contains(&frame));
+            run_frame(&mut cpu, &mut history);
+        }
+        assert_eq!(history.rewind(&mut cpu, 6), Ok(6));
+        for frame in 4..16 {
+            held.set((4..12).contains(&frame));
+            run_frame(&mut cpu, &mut history);
+        }
+        let expected = savestate::save(&cpu);
+        drop(cpu);
+
+        // the movie only has the timeline that was kept
+        let movie = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).ok();
+        let playback = ScriptedInput::parse(&movie).unwrap();
+        let mut cpu = counter(Box::new(playback));
+        let mut history = History::new(0);
+        for _ in 0..16 {
+            run_frame(&mut cpu, &mut history);
+        }
+        assert!(savestate::save(&cpu) == expected, "movie:\n{}", movie);
+    }
+}
@@ -0,0 +1,52 @@
+use savestate;
+
+// The random number generator behind Cxkk.
+//
+// A plain xorshift128 (Marsaglia, 2003), owned by the machine, so that runs
+// are reproducible given the same seed, and the generator's state can be
+// saved / restored along with everything else.
+
+pub struct Rng {
+    state: [u32; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift can't be seeded with all zeros, so pad the seed out with
+        // some arbitrary non-zero constants
+        Rng {
+            state: [seed as u32, (seed >> 32) as u32, 0x2545F491, 0x9E3779B9],
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let [x, y, z, w] = self.state;
+        let t = x ^ (x << 11);
+        let next = w ^ (w >> 19) ^ (t ^ (t >> 8));
+        self.state = [y, z, w, next];
+        next
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.next_u32() as u8
+    }
+
+    pub fn save_state(&self, w: &mut savestate::Writer) {
+        for word in &self.state {
+            w.u32(*word);
+        }
+    }
+
+    pub fn load_state(r: &mut savestate::Reader) -> Result<Rng, String> {
+        let mut state = [0; 4];
+        for word in state.iter_mut() {
+            *word = r.u32()?;
+        }
+
+        if state == [0; 4] {
+            return Err("[State] Invalid RNG state".to_string());
+        }
+
+        Ok(Rng { state })
+    }
+}
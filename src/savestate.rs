@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+
+use cpu::CPU;
+
+// Save states are a snapshot of the complete machine, in a simple binary
+// format:
+//
+//   "AC8E" magic, u16 format version, then each component's state, as
+//   written by its `save_state` method (CPU, RAM, ScreenRAM, Input).
+//
+// All multi-byte values are big-endian, just like CHIP-8 itself.
+// VERSION must be bumped whenever the layout changes.
+
+const MAGIC: &[u8; 4] = b"AC8E";
+const VERSION: u16 = 2;
+
+/* ----------  Serialization helpers  ---------- */
+
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { buf: vec![] }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.buf.extend_from_slice(val);
+    }
+
+    // length-prefixed byte buffer
+    pub fn blob(&mut self, val: &[u8]) {
+        self.u32(val.len() as u32);
+        self.bytes(val);
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.buf.len() {
+            return Err("[State] Unexpected end of save state".to_string());
+        }
+
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok((b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(b.iter().fold(0, |acc, byte| acc << 8 | *byte as u32))
+    }
+
+    // fill a fixed-size array
+    pub fn array(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let len = out.len();
+        out.copy_from_slice(self.bytes(len)?);
+        Ok(())
+    }
+
+    pub fn blob(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+/* ----------  Save / Load  ---------- */
+
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.bytes(MAGIC);
+    w.u16(VERSION);
+    cpu.save_state(&mut w);
+    w.into_bytes()
+}
+
+pub fn load(cpu: &mut CPU, data: &[u8]) -> Result<(), String> {
+    let mut r = Reader::new(data);
+
+    if r.bytes(MAGIC.len())? != MAGIC {
+        return Err("[State] Not an AC8E save state".to_string());
+    }
+
+    let version = r.u16()?;
+    if version != VERSION {
+        return Err(format!("[State] Unsupported save state version {} \
+                            (expected {})",
+                           version,
+                           VERSION));
+    }
+
+    cpu.load_state(&mut r)
+}
+
+pub fn save_file(cpu: &CPU, path: &str) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut f| f.write_all(&save(cpu)))
+        .map_err(|e| format!("[State] Couldn't write '{}': {}", path, e))
+}
+
+pub fn load_file(cpu: &mut CPU, path: &str) -> Result<(), String> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("[State] Couldn't read '{}': {}", path, e))?;
+
+    load(cpu, &data)
+}
+
+// Numbered save slots live next to the ROM
+pub fn slot_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::Write;
+
+use display;
+use display::ScreenRAM;
+use display::PALETTE;
+
+// Dumps the screen, either as text (using the same glyphs as the text
+// renderers), or as a Netpbm image, picked based on the file extension:
+//
+//   .pbm - black and white, lit pixels are black
+//   .ppm - full color, one color per XO-CHIP plane combination
+//   else - text
+
+pub fn text(screen: &ScreenRAM) -> String {
+    let mut out = String::new();
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            out.push(display::GLYPHS[screen.pixel(x, y) as usize]);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn pbm(screen: &ScreenRAM) -> Vec<u8> {
+    let mut out = format!("P1\n{} {}\n", screen.width(), screen.height());
+    for y in 0..screen.height() {
+        let row = (0..screen.width())
+            .map(|x| if screen.pixel(x, y) != 0 { "1" } else { "0" })
+            .collect::<Vec<&str>>();
+        out += &row.join(" ");
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+pub fn ppm(screen: &ScreenRAM) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", screen.width(), screen.height())
+        .into_bytes();
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            out.extend_from_slice(&PALETTE[screen.pixel(x, y) as usize]);
+        }
+    }
+    out
+}
+
+// Writes the screen to `path`, or prints it to stdout if `path` is "-"
+pub fn write(screen: &ScreenRAM, path: &str) -> Result<(), String> {
+    if path == "-" {
+        print!("{}", text(screen));
+        return Ok(());
+    }
+
+    let data = if path.ends_with(".pbm") {
+        pbm(screen)
+    } else if path.ends_with(".ppm") {
+        ppm(screen)
+    } else {
+        text(screen).into_bytes()
+    };
+
+    File::create(path)
+        .and_then(|mut f| f.write_all(&data))
+        .map_err(|e| format!("[Screenshot] Couldn't write '{}': {}", path, e))
+}
@@ -0,0 +1,84 @@
+#![allow(dead_code)]
+
+#[cfg(feature = "ncurses")]
+extern crate ncurses;
+
+use std::io;
+use std::io::Write;
+
+use audio::Audio;
+
+/*=====================================
+=            Sound Traits            =
+=====================================*/
+
+// Trait exposed to main loop to make some noise
+pub trait Play {
+    // run every frame. `beep` is set while the sound timer is non-zero
+    fn play(&self, beep: bool, audio: &Audio);
+}
+
+/*=====================================
+=            Sound Methods            =
+=====================================*/
+
+// None of the terminal frontends can actually play back XO-CHIP audio
+// patterns, so they all fall back to ringing the terminal bell.
+
+/* ----------  Null Sound  ---------- */
+
+pub struct NullSound {}
+
+impl NullSound {
+    pub fn new() -> NullSound {
+        NullSound {}
+    }
+}
+
+impl Play for NullSound {
+    fn play(&self, _beep: bool, _audio: &Audio) {}
+}
+
+/* ----------  Terminal Bell  ---------- */
+
+pub struct TermBell {}
+
+impl TermBell {
+    pub fn new() -> TermBell {
+        TermBell {}
+    }
+}
+
+impl Play for TermBell {
+    fn play(&self, beep: bool, _audio: &Audio) {
+        // no newline, so it doesn't disturb whatever is on screen
+        if beep {
+            print!("\u{0007}");
+            io::stdout().flush().ok();
+        }
+    }
+}
+
+/* ----------  Ncurses Beep  ---------- */
+
+#[cfg(feature = "ncurses")]
+pub struct NcursesBeep {}
+
+#[cfg(feature = "ncurses")]
+impl NcursesBeep {
+    pub fn new() -> NcursesBeep {
+        NcursesBeep {}
+    }
+}
+
+#[cfg(feature = "ncurses")]
+use self::ncurses as nc;
+
+#[cfg(feature = "ncurses")]
+impl Play for NcursesBeep {
+    fn play(&self, beep: bool, _audio: &Audio) {
+        if beep {
+            nc::beep();
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use savestate;
+
+// The call stack.
+//
+// A fixed number of slots for return addresses (12 or 16, depending on the
+// quirks), used as a ring: `top` is where the next one goes, and wraps back
+// around to the first slot, so once the stack is full, pushing overwrites the
+// oldest return address (if the quirks allow it at all).
+
+pub struct Stack {
+    slots: Vec<u16>,
+    top: usize, // next slot to push into
+    len: usize,
+}
+
+impl Stack {
+    pub fn new(depth: usize) -> Stack {
+        Stack {
+            slots: vec![0; depth],
+            top: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.slots.len()
+    }
+
+    pub fn push(&mut self, addr: u16) {
+        self.slots[self.top] = addr;
+        self.top = (self.top + 1) % self.slots.len();
+        self.len = (self.len + 1).min(self.slots.len());
+    }
+
+    pub fn pop(&mut self) -> Option<u16> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.top = (self.top + self.slots.len() - 1) % self.slots.len();
+        self.len -= 1;
+        Some(self.slots[self.top])
+    }
+
+    // The return addresses, oldest first
+    pub fn entries(&self) -> Vec<u16> {
+        let depth = self.slots.len();
+        (0..self.len)
+            .map(|n| self.slots[(self.top + depth - self.len + n) % depth])
+            .collect()
+    }
+
+    pub fn save_state(&self, w: &mut savestate::Writer) {
+        w.u16(self.len as u16);
+        for addr in self.entries() {
+            w.u16(addr);
+        }
+    }
+
+    pub fn load_state(r: &mut savestate::Reader, depth: usize)
+                      -> Result<Stack, String> {
+        let len = r.u16()? as usize;
+        if len > depth {
+            return Err(format!("[State] Stack is {} deep, only {} fits",
+                               len,
+                               depth));
+        }
+
+        let mut stack = Stack::new(depth);
+        for _ in 0..len {
+            stack.push(r.u16()?);
+        }
+        Ok(stack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stack;
+
+    #[test]
+    fn wraps_around() {
+        let mut stack = Stack::new(4);
+        for addr in 1..=6 {
+            stack.push(addr);
+        }
+
+        // 1 and 2 were overwritten
+        assert!(stack.is_full());
+        assert_eq!(stack.entries(), vec![3, 4, 5, 6]);
+        assert_eq!(stack.pop(), Some(6));
+        assert_eq!(stack.entries(), vec![3, 4, 5]);
+
+        stack.push(7);
+        assert_eq!(stack.entries(), vec![3, 4, 5, 7]);
+        for addr in &[7, 5, 4, 3] {
+            assert_eq!(stack.pop(), Some(*addr));
+        }
+        assert_eq!(stack.pop(), None);
+    }
+}
@@ -0,0 +1,141 @@
+use cpu::CPU;
+use types::Chip8Utils;
+
+// Decides how many instructions get to run each frame.
+//
+// By default, that's a fixed number of instructions per frame. Alternatively,
+// the speed of the original COSMAC VIP interpreter can be approximated, by
+// charging each instruction the number of 1802 machine cycles it took, out
+// of the cycles available in each frame.
+//
+// The VIP runs at 1.76 MHz, and a machine cycle is 8 clocks, which comes out
+// to ~3668 machine cycles per 60 Hz frame. The CDP1861 display steals 1024 of
+// those for DMA, and its interrupt routine (which also handles the timers)
+// takes another ~46, leaving the rest for the interpreter.
+//
+// Instruction costs are approximations of the timings measured on real
+// hardware, in machine cycles.
+
+const VIP_FRAME_CYCLES: i64 = 3668 - 1024 - 46;
+
+// Fetching and decoding an instruction
+const VIP_FETCH: u32 = 40;
+
+pub enum Pacing {
+    Fixed(u32), // instructions per frame
+    Vip,
+}
+
+pub struct Budget {
+    pacing: Pacing,
+    left: i64, // instructions, or machine cycles, left in this frame
+    frame_start: bool,
+}
+
+impl Budget {
+    pub fn new(pacing: Pacing) -> Budget {
+        Budget {
+            pacing,
+            left: 0,
+            frame_start: true,
+        }
+    }
+
+    pub fn start_frame(&mut self) {
+        match self.pacing {
+            Pacing::Fixed(ipf) => self.left = ipf as i64,
+            // overshooting the last frame eats into this one
+            Pacing::Vip => self.left = self.left.min(0) + VIP_FRAME_CYCLES,
+        }
+        self.frame_start = true;
+    }
+
+    // Returns true if the next instruction gets to run in this frame
+    pub fn next(&mut self, cpu: &CPU) -> bool {
+        if self.left <= 0 {
+            return false;
+        }
+
+        match self.pacing {
+            Pacing::Fixed(_) => self.left -= 1,
+            Pacing::Vip => {
+                let instr = cpu.ram().load_u16(cpu.pc()).unwrap_or(0);
+
+                // DRW waits for the display interrupt before drawing, so
+                // sprites only ever get drawn at the start of a frame
+                if instr.nibble_at(0) == 0xD && !self.frame_start {
+                    self.left = 0;
+                    return false;
+                }
+
+                self.left -= vip_cycles(instr, cpu) as i64;
+            }
+        }
+
+        self.frame_start = false;
+        true
+    }
+}
+
+// Machine cycles taken by an instruction, given the state it will run in
+fn vip_cycles(instr: u16, cpu: &CPU) -> u32 {
+    let x = instr.nibble_at(1) as usize;
+    let y = instr.nibble_at(2) as usize;
+    let n = instr.nibble_at(3) as u32;
+    let kk = instr as u8;
+    let nnn = instr & 0x0FFF;
+    let v = cpu.v();
+
+    // taking a skip costs a little extra
+    let skip = |taken: bool| if taken { 4 } else { 0 };
+    let key = |vx: u8| cpu.frontend().input.pressed_key(vx);
+
+    let cycles = match instr.nibble_at(0) {
+        0x0 => match instr {
+            0x00E0 => 678, // clears the 256 bytes of display RAM
+            0x00EE => 10,
+            _ => 10, // machine code subroutines are anyone's guess
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 => 10 + skip(v[x] == kk),
+        0x4 => 10 + skip(v[x] != kk),
+        0x5 => 14 + skip(v[x] == v[y]),
+        0x6 => 6,
+        0x7 => 10,
+        // runs a small routine built in RAM
+        0x8 => 44,
+        0x9 => 14 + skip(v[x] != v[y]),
+        0xA => 12,
+        0xB => {
+            let crosses_page = (nnn + v[0] as u16) & 0xF00 != nnn & 0xF00;
+            22 + if crosses_page { 2 } else { 0 }
+        }
+        0xC => 36,
+        0xD => {
+            // sprites that aren't byte-aligned have to be shifted into place,
+            // which roughly doubles the cost of each row
+            let row = if v[x] & 7 == 0 { 34 } else { 68 };
+            26 + row * n
+        }
+        0xE => match kk {
+            0x9E => 14 + skip(key(v[x])),
+            0xA1 => 14 + skip(!key(v[x])),
+            _ => 10,
+        },
+        0xF => match kk {
+            0x1E => 16,
+            0x29 => 16,
+            // BCD works by repeated subtraction, one loop per unit
+            0x33 => {
+                let digits = v[x] / 100 + v[x] / 10 % 10 + v[x] % 10;
+                80 + 16 * digits as u32
+            }
+            0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+            _ => 10,
+        },
+        _ => unreachable!(),
+    };
+
+    VIP_FETCH + cycles
+}
@@ -0,0 +1,95 @@
+extern crate libc;
+
+use std::io;
+use std::io::Write;
+use std::mem;
+
+// Bare-bones terminal control, for what ncurses doesn't do (or when it isn't
+// used at all): switching the tty in and out of raw mode, reading keys as
+// they come in, and sending escape codes.
+
+pub type Termios = libc::termios;
+
+// Asks terminals that speak the kitty keyboard protocol for key press, repeat
+// and release events, as escape codes (flags 1 | 2 | 8), and asks whether they
+// do, which they answer with `ESC [ ? <flags> u` (see input::Keyboard).
+// Other terminals ignore both.
+//
+// The flags are kept per screen, so this must be sent after switching to the
+// alternate screen, and taken back before switching away from it.
+pub const KITTY_KEYS_ON: &str = "\x1b[>11u\x1b[?u";
+pub const KITTY_KEYS_OFF: &str = "\x1b[<u";
+
+// Sends escape codes / output to the terminal right away
+pub fn write(out: &str) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(out.as_bytes())
+        .and_then(|_| stdout.flush())
+        .ok();
+}
+
+// Puts the terminal into raw mode: no echo, no line buffering, and no
+// signals (F1 quits, like with ncurses). Returns the old settings to restore
+// later, or None if stdin isn't a terminal.
+pub fn enable_raw_mode() -> Option<Termios> {
+    unsafe {
+        let mut old: Termios = mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut old) != 0 {
+            return None;
+        }
+
+        let mut raw = old;
+        libc::cfmakeraw(&mut raw);
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw);
+        Some(old)
+    }
+}
+
+pub fn restore(termios: &Termios) {
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, termios);
+    }
+}
+
+// Reads whatever input is waiting into `buf`, first waiting for some to
+// arrive if `block` is set. Returns how many bytes were read.
+//
+// This goes straight to the file descriptor, since anything sitting in
+// std's buffered stdin would be invisible to poll().
+pub fn read_input(buf: &mut [u8], block: bool) -> usize {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    unsafe {
+        if libc::poll(&mut fd, 1, if block { -1 } else { 0 }) <= 0 {
+            return 0;
+        }
+
+        let n = libc::read(libc::STDIN_FILENO,
+                           buf.as_mut_ptr() as *mut libc::c_void,
+                           buf.len());
+        n.max(0) as usize
+    }
+}
+
+// Takes over the terminal for a full screen renderer: raw mode, the alternate
+// screen (so whatever was there before comes back on exit), no cursor, and
+// key releases. Returns the old settings, for leave_fullscreen().
+pub fn enter_fullscreen() -> Option<Termios> {
+    let termios = enable_raw_mode();
+    write("\x1b[?1049h\x1b[?25l\x1b[2J");
+    write(KITTY_KEYS_ON);
+    termios
+}
+
+pub fn leave_fullscreen(termios: Option<Termios>) {
+    write(KITTY_KEYS_OFF);
+    write("\x1b[0m\x1b[?25h\x1b[?1049l");
+    if let Some(termios) = termios {
+        restore(&termios);
+    }
+}
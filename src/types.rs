@@ -11,7 +11,7 @@ impl Chip8Utils for u16 {
     }
     fn nibble_at(&self, i: u8) -> u8 {
         (match i {
-             3 => (*self & 0x000F),
+             3 => *self & 0x000F,
              2 => (*self & 0x00F0) >> 4,
              1 => (*self & 0x0F00) >> 8,
              0 => (*self & 0xF000) >> 12,
@@ -0,0 +1,343 @@
+use std::fs::File;
+use std::io::Read;
+
+use audio::Audio;
+use cdp1802::Bus;
+use cdp1802::Cdp1802;
+use cli::Options;
+use clock::Clock;
+use display::ScreenRAM;
+use frontend::Frontend;
+use input::Input;
+use screenshot;
+
+// COSMAC VIP system emulation.
+//
+// Instead of emulating CHIP-8 directly, this emulates the machine it was
+// written for: an RCA CDP1802 CPU, a CDP1861 video chip, and the VIP's hex
+// keypad, running the original 512-byte CHIP-8 interpreter (supplied by the
+// user, since it can't be distributed). Handy as a reference to test the
+// CHIP-8 core against.
+//
+// Memory map:
+//   0000 - 0FFF  4 KiB of RAM (mirrored up to 7FFF). The interpreter lives
+//                at 0000, and CHIP-8 programs at 0200
+//   8000 - 81FF  monitor ROM (mirrored up to FFFF)
+//
+// The interpreter relies on a single routine from the monitor ROM: the
+// display interrupt handler at 8146, which points the 1861 at the display
+// buffer, and counts down the delay / sound timers (in R8.1 / R8.0). Unless a
+// real monitor ROM is supplied, a stand-in written for this emulator is used.
+
+const RAM_SIZE: usize = 0x1000;
+
+// The 1861 draws 262 lines a frame, each taking 14 machine cycles. It
+// requests an interrupt 2 lines before the display starts, and then DMAs 8
+// bytes (one row of 64 pixels) out of memory for each of 128 lines.
+const LINE_CYCLES: u32 = 14;
+const FRAME_CYCLES: u32 = 262 * LINE_CYCLES;
+const INTERRUPT_LINE: u32 = 78;
+const DISPLAY_LINE: u32 = 80;
+const DISPLAY_LINES: u32 = 128;
+const DMA_CYCLES: u32 = 8;
+// From the interrupt being taken, to the first line of DMA
+const INTERRUPT_TO_DISPLAY: u32 = 29;
+
+// Stand-in for the monitor's interrupt routine. It keeps exactly in step
+// with the 1861: 3 instructions (6 cycles) between each line of DMA.
+const INTERRUPT_ROUTINE_ADDR: usize = 0x144;
+#[rustfmt::skip]
+static INTERRUPT_ROUTINE: [u8; 47] = [
+    // 8144: exit, leaving R1 pointing at the entry point
+    0x72,             // LDXA         restore D
+    0x70,             // RET          restore X, P, and enable interrupts
+    // 8146: entry point
+    0x22, 0x78,       // DEC 2, SAV   save X, P
+    0x22, 0x52,       // DEC 2, STR 2 save D
+    0xF8, 0x00, 0xA0, // LDI 00, PLO 0
+    0x9B, 0xB0,       // GHI B, PHI 0 R0 = display buffer (page in RB.1)
+    0xE2, 0xE2, 0xE2, // SEX 2 x3     wait for the display to start
+    // 8152: show each row of 8 bytes on 4 lines, by resetting R0
+    0x80, 0xE2, 0xE2, // GLO 0        -- line 0
+    0xA0, 0xE2, 0xE2, // PLO 0        -- line 1
+    0xA0, 0xE2, 0xE2, // PLO 0        -- line 2
+    0xA0, 0xE2,       // PLO 0
+    0x3C, 0x52,       // BN1 8152     -- line 3, until the display ends
+    // 815F: count down the timers (without touching DF)
+    0x98, 0x32, 0x66, // GHI 8, BZ 8166
+    0xA0, 0x20,       // PLO 0, DEC 0
+    0x80, 0xB8,       // GLO 0, PHI 8 delay timer - 1
+    // 8166
+    0x88, 0x32, 0x70, // GLO 8, BZ 8170
+    0xA0, 0x20,       // PLO 0, DEC 0
+    0x80, 0xA8,       // GLO 0, PLO 8 sound timer - 1
+    0x7B,             // SEQ          beep
+    0x30, 0x44,       // BR 8144
+    // 8170
+    0x7A,             // REQ          stop beeping
+    0x30, 0x44,       // BR 8144
+];
+
+/* ----------  Bus  ---------- */
+
+struct VipBus {
+    ram: Vec<u8>,
+    monitor: Vec<u8>,
+
+    display_on: bool, // toggled by INP 1 / OUT 1
+    ef1: bool,        // set by the 1861 just before the display starts / ends
+
+    key_latch: u8, // key selected with OUT 2, read back through EF3
+    keys: [bool; 16],
+}
+
+impl Bus for VipBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        if addr & 0x8000 != 0 {
+            self.monitor[addr % self.monitor.len()]
+        } else {
+            self.ram[addr % RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let addr = addr as usize;
+        if addr & 0x8000 == 0 {
+            self.ram[addr % RAM_SIZE] = val;
+        }
+    }
+
+    fn output(&mut self, port: u8, val: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.key_latch = val & 0xF,
+            _ => (),
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn ef(&self, flag: u8) -> bool {
+        match flag {
+            1 => self.ef1,
+            3 => self.keys[self.key_latch as usize],
+            _ => false,
+        }
+    }
+}
+
+/* ----------  Machine  ---------- */
+
+pub struct Vip {
+    cpu: Cdp1802,
+    bus: VipBus,
+    screen: ScreenRAM,
+
+    cycle: u32, // machine cycles into the current frame
+    interrupt_requested: bool,
+    display_start: Option<u32>, // cycle the first line of DMA is due
+    line: u32,                  // next line of DMA
+}
+
+impl Vip {
+    pub fn new(interpreter: &[u8],
+               monitor: Option<&[u8]>,
+               rom: &[u8],
+               load_addr: u16)
+               -> Result<Vip, String> {
+        if interpreter.len() > 0x200 {
+            return Err("[VIP] The interpreter must fit in 512 bytes"
+                .to_string());
+        }
+        if load_addr as usize + rom.len() > RAM_SIZE {
+            return Err("[VIP] The ROM doesn't fit in RAM".to_string());
+        }
+
+        let mut ram = vec![0; RAM_SIZE];
+        ram[..interpreter.len()].copy_from_slice(interpreter);
+        ram[load_addr as usize..load_addr as usize + rom.len()]
+            .copy_from_slice(rom);
+
+        let monitor = match monitor {
+            Some(monitor) if !monitor.is_empty() => monitor.to_vec(),
+            Some(_) => return Err("[VIP] The monitor ROM is empty".to_string()),
+            None => {
+                let mut monitor = vec![0; 0x200];
+                let routine = INTERRUPT_ROUTINE_ADDR..INTERRUPT_ROUTINE_ADDR +
+                                                     INTERRUPT_ROUTINE.len();
+                monitor[routine].copy_from_slice(&INTERRUPT_ROUTINE);
+                monitor
+            }
+        };
+
+        // The monitor hands over to the interpreter with the page of the
+        // display buffer (the last page of RAM) in R1.1
+        let mut cpu = Cdp1802::new();
+        cpu.r[1] = ((RAM_SIZE >> 8) as u16 - 1) << 8;
+
+        Ok(Vip {
+            cpu,
+            bus: VipBus {
+                ram,
+                monitor,
+
+                display_on: false,
+                ef1: false,
+
+                key_latch: 0,
+                keys: [false; 16],
+            },
+            screen: ScreenRAM::new(),
+
+            cycle: 0,
+            interrupt_requested: false,
+            display_start: None,
+            line: 0,
+        })
+    }
+
+    pub fn screen(&self) -> &ScreenRAM {
+        &self.screen
+    }
+
+    // The VIP's beeper is driven by Q
+    pub fn is_beeping(&self) -> bool {
+        self.cpu.q
+    }
+
+    pub fn run_frame(&mut self, input: &dyn Input) {
+        for key in 0..16 {
+            self.bus.keys[key] = input.pressed_key(key as u8);
+        }
+
+        while self.cycle < FRAME_CYCLES {
+            self.update_1861();
+            self.cycle += self.cpu.step(&mut self.bus);
+        }
+
+        // overshooting this frame eats into the next one
+        self.cycle -= FRAME_CYCLES;
+        self.interrupt_requested = false;
+        self.display_start = None;
+        self.line = 0;
+    }
+
+    // Interrupts, DMA and EF1, in between CPU instructions
+    fn update_1861(&mut self) {
+        if !self.bus.display_on {
+            return;
+        }
+
+        let interrupt_at = INTERRUPT_LINE * LINE_CYCLES;
+        let display_at = DISPLAY_LINE * LINE_CYCLES;
+
+        if self.display_start.is_none() {
+            // the interrupt request is held until the display starts
+            if self.cycle >= interrupt_at && self.cycle < display_at &&
+               !self.interrupt_requested && self.cpu.interrupt() {
+                self.interrupt_requested = true;
+                self.display_start = Some(self.cycle + INTERRUPT_TO_DISPLAY);
+                self.cycle += 1;
+            } else if self.cycle >= display_at {
+                // nobody took the interrupt, the display starts regardless
+                self.display_start = Some(display_at);
+            }
+        }
+
+        let start = match self.display_start {
+            Some(start) => start,
+            None => {
+                self.bus.ef1 = self.cycle >= display_at - 4 * LINE_CYCLES;
+                return;
+            }
+        };
+
+        while self.line < DISPLAY_LINES &&
+              self.cycle >= start + self.line * LINE_CYCLES {
+            let y = (self.line / 4) as usize;
+            for byte in 0..8 {
+                let bits = self.cpu.dma_out(&mut self.bus);
+                for bit in 0..8 {
+                    let lit = bits & (0x80 >> bit) != 0;
+                    self.screen.set_pixel(byte * 8 + bit, y, lit as u8);
+                }
+            }
+
+            self.line += 1;
+            self.cycle += DMA_CYCLES;
+        }
+
+        // EF1 is also set for the last 4 lines of the display
+        let end = start + DISPLAY_LINES * LINE_CYCLES;
+        self.bus.ef1 = self.cycle >= end - 4 * LINE_CYCLES && self.cycle < end;
+    }
+}
+
+/* ----------  Runner  ---------- */
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("couldn't read '{}': {}", path, e))?;
+    Ok(data)
+}
+
+// Runs a ROM on the VIP until the user quits, or it runs for as many frames
+// as were asked for
+pub fn run(opts: &Options, frontend: Frontend, rom: &[u8])
+           -> Result<(), String> {
+    let interpreter = match opts.vip_interpreter {
+        Some(ref path) => read_file(path)?,
+        None => return Err("[VIP] No interpreter given".to_string()),
+    };
+    let monitor = match opts.vip_monitor {
+        Some(ref path) => Some(read_file(path)?),
+        None => None,
+    };
+
+    let mut vip = Vip::new(&interpreter,
+                           monitor.as_deref(),
+                           rom,
+                           opts.load_addr)?;
+
+    // the VIP's display runs at a fixed 60 Hz
+    let mut clock = Clock::new(60);
+    let audio = Audio::new();
+    let mut frames = 0;
+
+    frontend.display.init();
+    loop {
+        let on_time = opts.headless || clock.wait();
+
+        frontend.input.update_keys(false);
+        if frontend.input.pressed_esc() {
+            break;
+        }
+
+        vip.run_frame(&*frontend.input);
+        frontend.input.decrement_keys();
+
+        if on_time {
+            frontend.display.render(vip.screen());
+            frontend.sound.play(vip.is_beeping(), &audio);
+        }
+
+        frames += 1;
+        if opts.frames.is_some_and(|limit| frames >= limit) {
+            break;
+        }
+    }
+    frontend.display.uninit();
+
+    match opts.dump {
+        Some(ref path) => screenshot::write(vip.screen(), path),
+        None => Ok(()),
+    }
+}